@@ -0,0 +1,57 @@
+// Shared resources of std `Atomic*` types are handed out to tasks directly, without a lock.
+// Resources of other `Sync` types (i.e. `crossbeam::atomic::AtomicCell`) can be marked with the
+// `#[atomic]` attribute to get the same treatment.
+
+#[rtic::app]
+mod app {
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    #[shared]
+    struct Shared {
+        counter: AtomicU32,
+        // The attribute is optional for std `Atomic*` types
+        #[atomic]
+        done: AtomicBool,
+    }
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        low::spawn().unwrap();
+
+        (
+            Shared {
+                counter: AtomicU32::new(0),
+                done: AtomicBool::new(false),
+            },
+            Local {},
+            init::Monotonics(),
+        )
+    }
+
+    #[task(shared = [counter, done])]
+    fn low(cx: low::Context) {
+        cx.shared.counter.fetch_add(1, Ordering::Relaxed);
+        high::spawn().unwrap();
+
+        // No lock is needed even though `high` has a higher priority
+        println!(
+            "low: counter = {}, done = {}",
+            cx.shared.counter.load(Ordering::Relaxed),
+            cx.shared.done.load(Ordering::Relaxed)
+        );
+    }
+
+    #[task(priority = 2, shared = [counter, done])]
+    fn high(cx: high::Context) {
+        cx.shared.counter.fetch_add(1, Ordering::Relaxed);
+        cx.shared.done.store(true, Ordering::Relaxed);
+
+        println!(
+            "high: counter = {}",
+            cx.shared.counter.load(Ordering::Relaxed)
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// Configuration is created by the `#[config]` function in the generated `main` and passed to
+// `#[init]`. With `main = false` it is passed to `app::start` instead.
+//
+// Run with `cargo run --example config -- 250` to change the period.
+
+use std::time::Duration;
+
+pub struct Config {
+    period: Duration,
+}
+
+#[rtic::app(config = super::Config)]
+mod app {
+    use super::Config;
+    use std::time::Duration;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        period: Duration,
+    }
+
+    #[config]
+    fn config() -> Config {
+        let millis = rtic::sched::args()
+            .nth(1)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(100);
+
+        Config {
+            period: Duration::from_millis(millis),
+        }
+    }
+
+    #[init]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        println!("period = {:?}", cx.config.period);
+        tick::spawn().unwrap();
+
+        (
+            Shared {},
+            Local {
+                period: cx.config.period,
+            },
+            init::Monotonics(),
+        )
+    }
+
+    #[task(local = [period])]
+    fn tick(cx: tick::Context) {
+        println!("tick");
+        tick::spawn_after(*cx.local.period).unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+// A control loop is released every 10ms and must complete within 10ms of its release. Every tenth
+// run takes too long, so the instances queued meanwhile are stale and skipped. Miss counters are
+// served for Prometheus, i.e. `curl localhost:9184/metrics`.
+
+#[rtic::app(metrics = "127.0.0.1:9184")]
+mod app {
+    use std::time::{Duration, Instant};
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        tick::spawn(Instant::now()).unwrap();
+
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[overrun]
+    fn overrun(overrun: &rtic::deadline::Overrun) {
+        println!("overrun: {}", overrun);
+    }
+
+    #[task(priority = 2)]
+    fn tick(_: tick::Context, release: Instant) {
+        control::spawn().ok();
+
+        let next = release + Duration::from_millis(10);
+        tick::spawn_at(next, next).unwrap();
+    }
+
+    #[task(priority = 1, capacity = 4, deadline = "10ms", overrun = "skip", local = [n: u32 = 0])]
+    fn control(cx: control::Context) {
+        *cx.local.n += 1;
+
+        let time = if cx.local.n.is_multiple_of(10) { 35 } else { 2 };
+        println!("control {} for {}ms", cx.local.n, time);
+        std::thread::sleep(Duration::from_millis(time));
+    }
+}
@@ -0,0 +1,36 @@
+// `#[init]` can return a `Result`. On error it is printed and the process exits before any
+// dispatcher thread is spawned.
+//
+// Run with `cargo run --example fallible_init -- /dev/missing` to see the error path.
+
+#[rtic::app]
+mod app {
+    use std::{fs::File, io::Read};
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        file: File,
+    }
+
+    #[init]
+    fn init(_: init::Context) -> Result<(Shared, Local, init::Monotonics), std::io::Error> {
+        let path = rtic::sched::args()
+            .nth(1)
+            .unwrap_or_else(|| "/proc/self/stat".to_string());
+        let file = File::open(path)?;
+
+        read::spawn().unwrap();
+
+        Ok((Shared {}, Local { file }, init::Monotonics()))
+    }
+
+    #[task(local = [file])]
+    fn read(cx: read::Context) {
+        let mut contents = String::new();
+        cx.local.file.read_to_string(&mut contents).unwrap();
+        println!("{}", contents.trim());
+    }
+}
@@ -0,0 +1,46 @@
+// Idle runs on the main thread after all dispatcher threads are initialized.
+// By default, init runs at the highest task priority and idle with `SCHED_OTHER` policy.
+
+#[rtic::app(init_priority = 10, idle_priority = 1)]
+mod app {
+    use std::time::Duration;
+
+    #[shared]
+    struct Shared {
+        counter: u32,
+    }
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        // Task will not run until idle is set up
+        tick::spawn().unwrap();
+
+        (Shared { counter: 0 }, Local {}, init::Monotonics())
+    }
+
+    #[idle(shared = [counter])]
+    fn idle(mut cx: idle::Context) -> ! {
+        loop {
+            // Set by Ctrl-C or SIGTERM. The process exits once all dispatchers have stopped and
+            // idle has acknowledged with `shutdown_done`.
+            if cx.shutdown_requested() {
+                let counter = cx.shared.counter.lock(|counter| *counter);
+                println!("idle: shutting down, counter = {}", counter);
+                cx.shutdown_done();
+            }
+
+            let counter = cx.shared.counter.lock(|counter| *counter);
+            println!("idle: counter = {}", counter);
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+
+    #[task(priority = 2, shared = [counter])]
+    fn tick(mut cx: tick::Context) {
+        cx.shared.counter.lock(|counter| *counter += 1);
+        tick::spawn_after(Duration::from_millis(100)).unwrap();
+    }
+}
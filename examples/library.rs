@@ -0,0 +1,59 @@
+// With `main = false` the application is started from user code, i.e. a larger program or a test.
+// The calling thread keeps its scheduling policy and can stop the application.
+
+use std::time::Duration;
+
+#[rtic::app(main = false)]
+mod app {
+    use std::time::Duration;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        tick::spawn(0).unwrap();
+
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[task(priority = 2)]
+    fn tick(_: tick::Context, n: u32) {
+        println!("tick {}", n);
+        tick::spawn_after(Duration::from_millis(100), n + 1).unwrap();
+    }
+}
+
+fn main() {
+    let handle = app::start(rtic::Config::default()).unwrap();
+
+    std::thread::sleep(Duration::from_millis(550));
+
+    let snapshot = app::introspect();
+    for level in &snapshot.levels {
+        println!(
+            "{}: {} queued, {} scheduled, running {:?}",
+            level.thread, level.queued, level.scheduled, level.running
+        );
+    }
+    for task in &snapshot.tasks {
+        println!(
+            "{}: {}/{} used, high-water {}, {} failed spawns",
+            task.name, task.used, task.capacity, task.high_water, task.spawn_failures
+        );
+    }
+
+    handle.request_shutdown();
+    let stats = handle.stats();
+    for thread in &stats.threads {
+        println!("{}: {} tasks", thread.name, thread.dispatched);
+    }
+    // Timing histograms are only collected with the `stats` feature
+    rtic::stats::print(&stats.tasks);
+
+    handle.join().unwrap();
+    println!("stopped");
+}
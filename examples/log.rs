@@ -0,0 +1,44 @@
+// Tasks log without blocking, the records are written by a low priority thread. A burst of more
+// records than the buffer holds is partially dropped and the drops are reported.
+// Set `RTIC_LOG=debug` to see the debug records.
+
+#[rtic::app]
+mod app {
+    use std::time::{Duration, Instant};
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        rtic::info!("starting with {} ticks per second", 10);
+        tick::spawn_after(Duration::from_millis(100)).unwrap();
+
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[task(priority = 2, local = [n: u32 = 0, last: Option<Instant> = None])]
+    fn tick(cx: tick::Context) {
+        *cx.local.n += 1;
+        let n = *cx.local.n;
+
+        let now = Instant::now();
+        if let Some(last) = cx.local.last.replace(now) {
+            rtic::debug!("tick {} after {:?}", n, now - last);
+        }
+        rtic::info!("tick {:>3} status {:#06x} {:?}", n, n * 31, "ok");
+
+        if n.is_multiple_of(10) {
+            rtic::warn!("burst of {} records", 1000);
+            for i in 0..1000u32 {
+                rtic::trace!("burst {}", i);
+                rtic::info!("burst {} of {}: {:.2}", i, 1000, i as f64 / 1000.0);
+            }
+        }
+
+        tick::spawn_after(Duration::from_millis(100)).unwrap();
+    }
+}
@@ -1,4 +1,11 @@
-#[rtic::app]
+// Memory is locked and prefaulted at startup, so that page faults do not add jitter to tasks.
+// Dispatcher threads are spawned with smaller stacks than the `std` default.
+#[rtic::app(
+    mlock = true,
+    prefault_stack = "256K",
+    prefault_heap = "8M",
+    stack_size = [(1, "512K")]
+)]
 mod app {
     use std::time::{Duration, Instant};
 
@@ -0,0 +1,48 @@
+// Large buffers are allocated from a static pool and passed between tasks by moving a handle.
+// Buffer is returned to the pool when the handle is dropped in the receiving task.
+
+#[rtic::app]
+mod app {
+    use rtic::pool::{Box, Pool};
+    use std::time::Duration;
+
+    const BUF_SIZE: usize = 4096;
+    const BUF_COUNT: usize = 4;
+
+    rtic::lazy_static::lazy_static! {
+        static ref BUFFERS: Pool<[u8; BUF_SIZE], BUF_COUNT> = Pool::new();
+    }
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        producer::spawn(0).unwrap();
+
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[task(priority = 2)]
+    fn producer(_: producer::Context, x: u8) {
+        // Pool is exhausted if consumer does not keep up
+        let buf = BUFFERS.alloc([x; BUF_SIZE]).unwrap();
+        consumer::spawn(buf).unwrap();
+
+        if x < 10 {
+            producer::spawn_after(Duration::from_millis(100), x + 1).unwrap();
+        }
+    }
+
+    #[task(capacity = 4)]
+    fn consumer(_: consumer::Context, buf: Box<[u8; BUF_SIZE], BUF_COUNT>) {
+        println!(
+            "consumer: buf[0] = {}, {} buffers available",
+            buf[0],
+            BUFFERS.available()
+        );
+    }
+}
@@ -0,0 +1,35 @@
+// Watchdog reports tasks that run longer than 100ms and dispatchers that stop making progress.
+// Without a `#[watchdog]` function the process is aborted instead.
+
+#[rtic::app(watchdog = "100ms")]
+mod app {
+    use std::time::Duration;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        work::spawn(1).unwrap();
+
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[watchdog]
+    fn stalled(stall: &rtic::watchdog::Stall) {
+        println!("watchdog: {}", stall);
+    }
+
+    #[task(priority = 2)]
+    fn work(_: work::Context, n: u64) {
+        // Every third run takes too long
+        let time = if n.is_multiple_of(3) { 300 } else { 10 };
+        println!("work {} for {}ms", n, time);
+        std::thread::sleep(Duration::from_millis(time));
+
+        work::spawn_after(Duration::from_millis(100), n + 1).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+use rtic_syntax::{analyze::Analysis, ast::App};
+use syn::{Error, Result};
+
+use crate::syntax::Extra;
+
+/// Stack size of threads spawned without an explicit size, the `std` default
+const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// Stack left for the frames that are active while prefaulting, `rtic::memory::STACK_MARGIN`
+const STACK_MARGIN: usize = 64 * 1024;
+
+/// Checks linux-rtic specific constraints that `rtic-syntax` does not know about
+pub fn app(app: &App, analysis: &Analysis, extra: &Extra) -> Result<()> {
+    // Idle shares resources with tasks, but the ceiling analysis assumes that it has the lowest priority
+    if let Some((&lowest, _)) = analysis.channels.iter().next() {
+        if app.idle.is_some() && extra.idle_priority >= lowest {
+            return Err(Error::new(
+                app.name.span(),
+                format!(
+                    "`idle_priority` must be lower than the lowest task priority ({})",
+                    lowest
+                ),
+            ));
+        }
+    }
+
+    for (priority, &(span, _)) in &extra.stack_sizes {
+        if !analysis.channels.contains_key(priority) {
+            return Err(Error::new(
+                span,
+                format!("there is no task with priority {}", priority),
+            ));
+        }
+    }
+
+    // Prefaulting beyond the end of the stack would overflow it. Threads without an explicit stack
+    // size get the `std` default, including idle in `app::start`. The main thread runs idle in
+    // `app::run`, its stack size is only known at runtime and checked by `prefault_stack`. Init
+    // does not prefault its stack.
+    if let Some(prefault) = extra.prefault_stack {
+        let dispatchers = analysis.channels.keys().map(|&priority| {
+            (
+                format!("priority {} thread", priority),
+                extra.stack_size(priority).unwrap_or(DEFAULT_STACK_SIZE),
+            )
+        });
+        let idle = app.idle.as_ref().map(|_| {
+            (
+                "idle thread of `app::start`".to_string(),
+                DEFAULT_STACK_SIZE,
+            )
+        });
+
+        for (thread, size) in dispatchers.chain(idle) {
+            if prefault + STACK_MARGIN > size {
+                return Err(Error::new(
+                    app.name.span(),
+                    format!(
+                        "`prefault_stack` must be at least {} bytes smaller than the stack size of the {} ({} bytes)",
+                        STACK_MARGIN, thread, size
+                    ),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
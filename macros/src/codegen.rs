@@ -1,7 +1,11 @@
-use proc_macro2::TokenStream;
+use proc_macro2::{Span, TokenStream};
 use quote::quote;
 use rtic_syntax::{analyze::Analysis, ast::App};
+use syn::Ident;
 
+use crate::syntax::Extra;
+
+mod assertions;
 mod dispatchers;
 mod idle;
 mod init;
@@ -14,35 +18,357 @@ mod shared_resources_struct;
 mod tasks;
 mod util;
 
-pub fn app(app: &App, analysis: &Analysis) -> TokenStream {
+pub fn app(app: &App, analysis: &Analysis, extra: &Extra) -> TokenStream {
     let app_name = &app.name;
 
     let user_imports = &app.user_imports;
     let user_code = &app.user_code;
 
-    let (init_defs, call_init) = init::codegen(app, analysis);
-    let (idle_defs, call_idle) = idle::codegen(app, analysis);
-    let tasks = tasks::codegen(app, analysis);
-    let dispatchers = dispatchers::codegen(app, analysis);
-    let post_init = post_init::codegen(app, analysis);
+    let assertions = assertions::codegen(app, analysis, extra);
+    let (init_defs, call_init) = init::codegen(app, analysis, extra);
+    let (idle_defs, call_idle) = idle::codegen(app, analysis, extra);
+    let tasks = tasks::codegen(app, analysis, extra);
+    let dispatchers = dispatchers::codegen(app, analysis, extra);
+    let post_init = post_init::codegen(app, analysis, extra);
+
+    let task_stats = app
+        .software_tasks
+        .iter()
+        .map(|(name, task)| {
+            let cfgs = &task.cfgs;
+            let ident = util::task_stats_ident(name);
+            quote!(
+                #(#cfgs)*
+                tasks.push(&#ident);
+            )
+        })
+        .collect::<Vec<_>>();
+    let task_deadlines = app
+        .software_tasks
+        .iter()
+        .filter(|(name, _)| extra.deadlines.contains_key(*name))
+        .map(|(name, task)| {
+            let cfgs = &task.cfgs;
+            let ident = util::task_deadline_ident(name);
+            quote!(
+                #(#cfgs)*
+                deadlines.push(&#ident);
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let lock_stats = app
+        .shared_resources
+        .iter()
+        .filter(|(name, res)| !res.properties.lock_free && !extra.atomic_resources.contains(*name))
+        .map(|(name, res)| {
+            let cfgs = &res.cfgs;
+            let ident = util::lock_stats_ident(name);
+            quote!(
+                #(#cfgs)*
+                locks.push(&#ident);
+            )
+        })
+        .collect::<Vec<_>>();
+    let task_names = app
+        .software_tasks
+        .keys()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let task_states = app
+        .software_tasks
+        .iter()
+        .map(|(name, task)| {
+            let cfgs = &task.cfgs;
+            let task_name = name.to_string();
+            let priority = task.args.priority;
+            let input_queue = util::task_input_queue_ident(name);
+            quote!(
+                #(#cfgs)*
+                tasks.push(rtic::introspect::Task::new(#task_name, #priority, &#input_queue.0));
+            )
+        })
+        .collect::<Vec<_>>();
 
     let mut spawn_threads = vec![];
-    spawn_threads.push(quote!(
-        let mut thread_handles = vec![];
-    ));
+    let mut level_states = vec![];
+    let mut thread_stats = vec![];
+    let mut shutdown_dispatchers = vec![];
+    let mut progress_refs = vec![];
+    let mut watchdog_dispatchers = vec![];
     for (&level, _channel) in &analysis.channels {
         let thread_ident = util::thread_ident(level);
-        let thread_name = util::thread_name(level);
+        let thread_name = util::thread_name(app_name, level);
+        let stack_size = match extra.stack_size(level) {
+            Some(size) => quote!(Some(#size)),
+            None => quote!(None),
+        };
         spawn_threads.push(quote!(
-            let thread = std::thread::Builder::new()
-                .name(#thread_name.to_string())
-                .spawn(#thread_ident);
+            (#thread_name, #stack_size, #thread_ident as fn())
+        ));
+
+        let progress = util::progress_ident(level);
+        thread_stats.push(quote!(
+            rtic::runtime::ThreadStats {
+                name: #thread_name,
+                priority: #level,
+                dispatched: #progress.dispatched(),
+            }
+        ));
+        progress_refs.push(quote!(&#progress));
+        level_states.push(quote!(
+            rtic::introspect::Level::new(#level, #thread_name, &#progress, TASKS)
+        ));
+        watchdog_dispatchers.push(quote!(
+            rtic::watchdog::Dispatcher {
+                name: #thread_name,
+                priority: #level,
+                progress: &#progress,
+            }
+        ));
 
-            thread_handles.push(thread);
+        let rq = util::run_queue_ident(level);
+        shutdown_dispatchers.push(quote!(
+            // Can not fail, one slot of each run queue is reserved for this
+            #rq.0.send(None).ok();
         ));
     }
 
-    let (mod_app_shared_resources, mod_shared_resources) = shared_resources::codegen(app, analysis);
+    // Init runs above all tasks by default, so that it is not preempted by the tasks it spawns
+    let init_priority = extra
+        .init_priority
+        .or_else(|| analysis.channels.keys().next_back().copied())
+        .unwrap_or(0);
+    let idle_priority = extra.idle_priority;
+    let max_priority = analysis
+        .channels
+        .keys()
+        .copied()
+        .chain([init_priority, idle_priority])
+        .max()
+        .unwrap_or(0);
+    let audit_policy = extra
+        .audit_policy
+        .clone()
+        .unwrap_or_else(|| Ident::new("Warn", Span::call_site()));
+    let thread_init_barrier = util::thread_init_barrier();
+
+    let mut memory = vec![];
+    if extra.mlock {
+        memory.push(quote!(if let Err(err) = rtic::memory::lock() {
+            eprintln!("rtic: failed to lock memory: {}", err);
+        }));
+    }
+    if let Some(size) = extra.prefault_heap {
+        memory.push(quote!(rtic::memory::prefault_heap(#size);));
+    }
+    let prefault_stack = extra
+        .prefault_stack
+        .map(|size| quote!(rtic::memory::prefault_stack::<#size>();));
+
+    let trace_thread_name = util::app_thread_name(app_name, "-trace");
+    let shm_thread_name = util::app_thread_name(app_name, "-shm");
+    let log_thread_name = util::app_thread_name(app_name, "-log");
+    let started = util::started_ident();
+    let shutdown_requested = util::shutdown_requested_ident();
+    let idle_stop = util::idle_stop_ident();
+    let request_shutdown = util::request_shutdown_ident();
+    let stats = util::stats_ident();
+    let init_ident = util::init_ident();
+    let spawn_threads_ident = util::spawn_threads_ident();
+
+    // Serves the same statistics as `AppHandle::stats` from a low priority thread
+    let metrics_thread_name = util::app_thread_name(app_name, "-metrics");
+    let metrics_endpoint = match &extra.metrics {
+        Some(endpoint) => quote!(Some(#endpoint.parse().unwrap())),
+        None => quote!(None::<rtic::metrics::Endpoint>),
+    };
+
+    // Run by the thread that runs idle, once all dispatchers are initialized. The watchdog is
+    // spawned after the dispatchers have marked themselves alive.
+    let spawn_watchdog = match extra.watchdog {
+        Some(timeout) => {
+            let thread_name = util::app_thread_name(app_name, "-wdog");
+            let handler = match &extra.watchdog_hook {
+                Some(hook) => quote!(#hook),
+                None => quote!(rtic::watchdog::abort),
+            };
+            quote!(
+                rtic::watchdog::spawn(
+                    #thread_name,
+                    std::time::Duration::from_nanos(#timeout),
+                    vec![#(#watchdog_dispatchers),*],
+                    &#shutdown_requested,
+                    #handler,
+                )
+            )
+        }
+        None => quote!(Ok::<(), std::io::Error>(())),
+    };
+    let notify_thread_name = util::app_thread_name(app_name, "-notify");
+    let notify_ready = quote!(
+        rtic::systemd::ready(#notify_thread_name, vec![#(#progress_refs),*], &#shutdown_requested);
+    );
+
+    // A failure after some threads were spawned stops them before the error is returned
+    let stop_background = quote!(
+        rtic::metrics::stop();
+        rtic::shm::stop();
+        rtic::trace::stop();
+        rtic::profiling::flush();
+        rtic::log::stop();
+    );
+    let spawn_failed = quote!(
+        Err(err) => {
+            #stop_background
+            return Err(rtic::Error::Spawn(err));
+        }
+    );
+
+    // Dispatchers are spawned but wait at the barrier, no task ran yet
+    let serve_metrics = |endpoint| {
+        quote!(
+            if let Some(endpoint) = #endpoint {
+                if let Err(err) = rtic::metrics::serve(#metrics_thread_name, endpoint, #stats, introspect) {
+                    rtic::runtime::abort_startup(&#thread_init_barrier, threads);
+                    #stop_background
+                    return Err(rtic::Error::Metrics(err));
+                }
+            }
+        )
+    };
+    let run_metrics = serve_metrics(metrics_endpoint.clone());
+    let start_metrics = serve_metrics(quote!(runtime.metrics.or(#metrics_endpoint)));
+
+    // Without `#[idle]` the calling thread only waits until all dispatchers are initialized
+    let start_idle = if app.idle.is_some() {
+        let idle_thread_name = util::app_thread_name(app_name, "-idle");
+        quote!(
+            let (initialized, wait_initialized) = std::sync::mpsc::channel();
+            let idle = rtic::runtime::spawn_thread(#idle_thread_name, None, move || unsafe {
+                rtic::init_thread_state(#idle_priority);
+                #prefault_stack
+
+                #thread_init_barrier.wait();
+                let ready = #spawn_watchdog;
+                let failed = ready.is_err();
+                initialized.send(ready).ok();
+                if failed {
+                    return;
+                }
+                #notify_ready
+
+                #call_idle
+            });
+            if let Err(err) = idle {
+                rtic::runtime::abort_startup(&#thread_init_barrier, threads);
+                #stop_background
+                return Err(rtic::Error::Spawn(err));
+            }
+            // Fails only if the idle thread panicked before the barrier
+            let ready = wait_initialized.recv().unwrap_or(Ok(()));
+        )
+    } else {
+        quote!(
+            #thread_init_barrier.wait();
+            let ready = #spawn_watchdog;
+            if ready.is_ok() {
+                #notify_ready
+            }
+        )
+    };
+    let init_thread_name = util::app_thread_name(app_name, "-init");
+
+    // Configuration of `#[init]` is an extra argument of `run` and `start`
+    let (config_param, config_arg) = match &extra.config {
+        Some(ty) => (Some(quote!(config: #ty)), Some(quote!(config))),
+        None => (None, None),
+    };
+    let start_config_param = extra.config.as_ref().map(|ty| quote!(, config: #ty));
+    let config_hook_ident = util::config_hook_ident();
+    let config_hook = match (&extra.config, &extra.config_hook) {
+        (Some(ty), Some(hook)) => Some(quote!(
+            #[doc(hidden)]
+            pub fn #config_hook_ident() -> #ty {
+                #hook()
+            }
+        )),
+        _ => None,
+    };
+    let profiling_hook_ident = util::profiling_hook_ident();
+    let profiling_hook = extra.profiling_hook.as_ref().map(|hook| {
+        quote!(
+            #[doc(hidden)]
+            pub fn #profiling_hook_ident() -> rtic::profiling::Profiling {
+                #hook()
+            }
+        )
+    });
+    let profiling = match &extra.profiling_hook {
+        Some(_) => quote!(#app_name::#profiling_hook_ident()),
+        None => quote!(rtic::profiling::Profiling::new()),
+    };
+    let main_config = match (&extra.config, &extra.config_hook) {
+        (Some(_), Some(_)) => Some(quote!(#app_name::#config_hook_ident())),
+        (Some(_), None) => Some(quote!(Default::default())),
+        _ => None,
+    };
+
+    // A fallible `#[init]` makes `run` and `start` return its error, after the background threads
+    // started before it are stopped
+    let (init_return, init_ok, error) = match &extra.init_error {
+        Some(error) => (
+            Some(quote!(-> Result<(), #error>)),
+            Some(quote!(Ok(()))),
+            quote!(rtic::Error<#error>),
+        ),
+        None => (None, None, quote!(rtic::Error)),
+    };
+    let run_init = if extra.init_error.is_some() {
+        quote!(
+            if let Err(err) = #init_ident(#config_arg) {
+                #stop_background
+                return Err(rtic::Error::Init(err));
+            }
+        )
+    } else {
+        quote!(#init_ident(#config_arg);)
+    };
+    let start_init = if extra.init_error.is_some() {
+        quote!(
+            match init.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => {
+                    #stop_background
+                    return Err(rtic::Error::Init(err));
+                }
+                Err(err) => std::panic::resume_unwind(err),
+            }
+        )
+    } else {
+        quote!(if let Err(err) = init.join() {
+            std::panic::resume_unwind(err);
+        })
+    };
+
+    // The only place where a failed startup exits the process
+    let main = if extra.library {
+        None
+    } else {
+        Some(quote!(
+            fn main() {
+                rtic::profiling::init(#profiling);
+
+                if let Err(err) = unsafe { #app_name::run(#main_config) } {
+                    eprintln!("rtic: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        ))
+    };
+
+    let (mod_app_shared_resources, mod_shared_resources) =
+        shared_resources::codegen(app, analysis, extra);
     let (mod_app_local_resources, mod_local_resources) = local_resources::codegen(app, analysis);
 
     quote!(
@@ -53,6 +379,8 @@ pub fn app(app: &App, analysis: &Analysis) -> TokenStream {
             /// Unaltered user code
             #(#user_code)*
 
+            #(#assertions)*
+
             #(#tasks)*
 
             #(#dispatchers)*
@@ -65,27 +393,182 @@ pub fn app(app: &App, analysis: &Analysis) -> TokenStream {
             #(#mod_app_shared_resources)*
             #(#mod_app_local_resources)*
 
-            #[allow(unreachable_code)]
-            pub unsafe fn run() {
+            #[doc(hidden)]
+            #[allow(non_upper_case_globals)]
+            static #started: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+            #[doc(hidden)]
+            #[allow(non_upper_case_globals)]
+            static #shutdown_requested: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+            #[doc(hidden)]
+            #[allow(non_upper_case_globals)]
+            static #idle_stop: rtic::runtime::IdleStop = rtic::runtime::IdleStop::new();
+
+            /// Stops dispatcher threads after the tasks already in their run queues
+            #[doc(hidden)]
+            fn #request_shutdown() {
+                if !#shutdown_requested.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                    rtic::systemd::stopping();
+                    #(#shutdown_dispatchers)*
+                }
+            }
+
+            #[doc(hidden)]
+            fn #stats() -> rtic::runtime::Stats {
+                let mut tasks = vec![];
+                #(#task_stats)*
+                let mut deadlines = vec![];
+                #(#task_deadlines)*
+                let mut locks = vec![];
+                #(#lock_stats)*
+
+                rtic::runtime::Stats {
+                    threads: vec![#(#thread_stats,)*],
+                    tasks,
+                    deadlines,
+                    locks,
+                }
+            }
+
+            /// Returns the current state of run queues and tasks
+            pub fn introspect() -> rtic::introspect::Snapshot {
+                const TASKS: &[&str] = &[#(#task_names),*];
+
+                let mut tasks = vec![];
+                #(#task_states)*
+
+                rtic::introspect::Snapshot {
+                    levels: vec![#(#level_states,)*],
+                    tasks,
+                }
+            }
+
+            /// Runs `#[init]` and moves the returned resources into place
+            #[doc(hidden)]
+            unsafe fn #init_ident(#config_param) #init_return {
+                rtic::init_thread_state(#init_priority);
+
                 #call_init
                 #(#post_init)*
-                #(#spawn_threads)*
+                #init_ok
+            }
+
+            #config_hook
+            #profiling_hook
+
+            #[doc(hidden)]
+            fn #spawn_threads_ident() -> std::io::Result<Vec<std::thread::JoinHandle<()>>> {
+                rtic::runtime::spawn_dispatchers(&#thread_init_barrier, vec![#(#spawn_threads,)*])
+            }
+
+            /// Runs the application on the calling thread, which becomes idle
+            ///
+            /// Returns only if the startup fails, i.e. the environment audit, a fallible `#[init]`
+            /// or spawning a thread.
+            ///
+            /// # Safety
+            ///
+            /// Must be called once, while no other thread uses the application.
+            #[allow(unreachable_code)]
+            pub unsafe fn run(#config_param) -> Result<std::convert::Infallible, #error> {
+                if #started.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                    panic!("application is already running");
+                }
+
+                rtic::sched::init(#max_priority, None);
+                rtic::environment::audit(rtic::environment::Policy::#audit_policy, #max_priority)
+                    .map_err(rtic::Error::Audit)?;
+                rtic::trace::start(#trace_thread_name);
+                rtic::log::start(#log_thread_name);
+
+                // Before `#[init]`, so that its allocations are locked too
+                #(#memory)*
+
+                #run_init
+                let threads = match #spawn_threads_ident() {
+                    Ok(threads) => threads,
+                    #spawn_failed
+                };
+                rtic::shm::publish(#shm_thread_name, #stats, introspect);
+                #run_metrics
+                let handle = rtic::AppHandle::new(threads, &#idle_stop, #request_shutdown, #stats, introspect);
+
+                // Idle runs on the main thread
+                rtic::init_thread_state(#idle_priority);
+                #prefault_stack
+
+                // Wait until all threads are initialized before any task can run
+                #thread_init_barrier.wait();
+                if let Err(err) = #spawn_watchdog {
+                    handle.request_shutdown();
+                    handle.join().ok();
+                    return Err(rtic::Error::Spawn(err));
+                }
+                #notify_ready
+
+                // Ctrl-C and SIGTERM stop the dispatchers in an orderly way
+                rtic::runtime::exit_on_signal(handle);
+
                 #call_idle
             }
-        }
 
-        fn main() {
-            #[cfg(feature = "profiling")]
-            let _guard = {
-                use rtic::tracing_subscriber::prelude::*;
-                let (chrome_layer, guard) = rtic::tracing_chrome::ChromeLayerBuilder::new().build();
-                let fmt_layer = rtic::tracing_subscriber::fmt::layer().with_target(false);
-                let filter_layer = rtic::tracing_subscriber::EnvFilter::from_default_env();
-                rtic::tracing_subscriber::registry().with(chrome_layer).with(filter_layer).with(fmt_layer).init();
-                guard
-            };
+            /// Starts the application on its own threads and returns once they are initialized
+            ///
+            /// `#[init]` and `#[idle]` run on separate threads, so the scheduling of the calling
+            /// thread is not changed. `#[idle]` is not stopped by the returned handle and keeps
+            /// running after [`rtic::AppHandle::join`] returns, see there how it is waited for.
+            ///
+            /// A failed environment audit or the error of a fallible `#[init]` is returned before
+            /// any dispatcher is spawned. If a thread fails to spawn, the threads spawned before it
+            /// are stopped and the error is returned. The application can not be started again
+            /// afterwards.
+            ///
+            /// # Panics
+            ///
+            /// If the application is already running.
+            pub fn start(runtime: rtic::Config #start_config_param) -> Result<rtic::AppHandle, #error> {
+                if #started.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                    panic!("application is already running");
+                }
+
+                rtic::sched::init(#max_priority, runtime.mode);
+                rtic::environment::audit(
+                    runtime.audit.unwrap_or(rtic::environment::Policy::#audit_policy),
+                    #max_priority,
+                )
+                .map_err(rtic::Error::Audit)?;
+                rtic::trace::start(#trace_thread_name);
+                rtic::log::start(#log_thread_name);
+
+                #(#memory)*
 
-            unsafe { #app_name::run(); }
+                let init = match rtic::runtime::spawn_thread(#init_thread_name, None, move || unsafe {
+                    #init_ident(#config_arg)
+                }) {
+                    Ok(init) => init,
+                    #spawn_failed
+                };
+                #start_init
+
+                let threads = match #spawn_threads_ident() {
+                    Ok(threads) => threads,
+                    #spawn_failed
+                };
+                rtic::shm::publish(#shm_thread_name, #stats, introspect);
+                #start_metrics
+                #start_idle
+
+                let handle = rtic::AppHandle::new(threads, &#idle_stop, #request_shutdown, #stats, introspect);
+                if let Err(err) = ready {
+                    handle.request_shutdown();
+                    handle.join().ok();
+                    return Err(rtic::Error::Spawn(err));
+                }
+                Ok(handle)
+            }
         }
+
+        #main
     )
 }
@@ -0,0 +1,71 @@
+use proc_macro2::TokenStream;
+use quote::quote_spanned;
+use rtic_syntax::{analyze::Analysis, ast::App};
+use std::collections::HashSet;
+use syn::spanned::Spanned;
+
+use crate::syntax::Extra;
+
+/// Generates compile time assertions that data crossing thread boundaries is `Send`
+///
+/// Errors are spanned to the offending type in the user code.
+pub fn codegen(app: &App, _analysis: &Analysis, extra: &Extra) -> Vec<TokenStream> {
+    let mut stmts = vec![];
+
+    // Resources used by software tasks are initialized in `#[init]`, but accessed from dispatcher threads
+    let mut shared_resources = HashSet::new();
+    let mut local_resources = HashSet::new();
+
+    for (_, task) in &app.software_tasks {
+        let cfgs = &task.cfgs;
+
+        // Task inputs are moved to the dispatcher thread
+        for input in &task.inputs {
+            let ty = &input.ty;
+            stmts.push(quote_spanned!(ty.span()=>
+                #(#cfgs)*
+                const _: () = rtic::assert_send::<#ty>();
+            ));
+        }
+
+        shared_resources.extend(task.args.shared_resources.keys());
+        local_resources.extend(task.args.local_resources.keys());
+    }
+
+    for (name, res) in &app.shared_resources {
+        // Atomic resources are asserted to be `Sync` instead
+        if !shared_resources.contains(name) || extra.atomic_resources.contains(name) {
+            continue;
+        }
+
+        let cfgs = &res.cfgs;
+        let ty = &res.ty;
+        stmts.push(quote_spanned!(ty.span()=>
+            #(#cfgs)*
+            const _: () = rtic::assert_send::<#ty>();
+        ));
+    }
+
+    // Only resources from the `#[local]` struct, declared ones are never moved
+    for (name, res) in &app.local_resources {
+        if !local_resources.contains(name) {
+            continue;
+        }
+
+        let cfgs = &res.cfgs;
+        let ty = &res.ty;
+        stmts.push(quote_spanned!(ty.span()=>
+            #(#cfgs)*
+            const _: () = rtic::assert_send::<#ty>();
+        ));
+    }
+
+    // `app::start` moves the configuration to the init thread
+    if let Some(ty) = &extra.config {
+        stmts.push(quote_spanned!(ty.span()=>
+            const _: () = rtic::assert_send::<#ty>();
+        ));
+    }
+
+    stmts
+}
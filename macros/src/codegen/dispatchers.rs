@@ -2,22 +2,27 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use rtic_syntax::{analyze::Analysis, ast::App};
 
-use crate::codegen::util;
+use crate::{codegen::util, syntax::Extra};
 
 /// Generates task dispatchers
-pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
+pub fn codegen(app: &App, analysis: &Analysis, extra: &Extra) -> Vec<TokenStream> {
     let mut stmts = vec![];
 
     let thread_init_barrier = util::thread_init_barrier();
-    let num_threads = analysis.channels.iter().count();
+    let overrun_handler = match &extra.overrun_hook {
+        Some(hook) => quote!(#hook),
+        None => quote!(rtic::deadline::ignore),
+    };
+    let prefault_stack = extra
+        .prefault_stack
+        .map(|size| quote!(rtic::memory::prefault_stack::<#size>();));
+    // Dispatcher threads and the main thread, which runs idle
+    let num_threads = analysis.channels.len() + 1;
     stmts.push(quote!(
         #[doc(hidden)]
-        #[allow(non_camel_case_types)]
         #[allow(non_upper_case_globals)]
-        rtic::lazy_static::lazy_static! {
-            static ref #thread_init_barrier: std::sync::Arc<std::sync::Barrier> =
-               std::sync::Arc::new(std::sync::Barrier::new(#num_threads));
-        }
+        static #thread_init_barrier: rtic::runtime::InitBarrier =
+            rtic::runtime::InitBarrier::new(#num_threads);
     ));
 
     for (&level, channel) in &analysis.channels {
@@ -26,10 +31,12 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
             .iter()
             .map(|name| {
                 let cfgs = &app.software_tasks[name].cfgs;
+                // Discriminant is the index of the task in the app, see `Progress::start`
+                let index = util::task_index(app, name);
 
                 quote!(
                     #(#cfgs)*
-                    #name
+                    #name = #index as isize
                 )
             })
             .collect::<Vec<_>>();
@@ -47,15 +54,16 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
             }
         ));
 
-        let capacity = channel
-            .capacity
+        // One extra slot for the shutdown request
+        let capacity = (channel.capacity as usize + 1)
             .checked_next_power_of_two()
             .expect("task capacity too high");
-        let capacity_lit = util::capacity_literal(capacity as usize);
+        let capacity_lit = util::capacity_literal(capacity);
         let rq = util::run_queue_ident(level);
-        let rq_send_ty =
-            quote!(rtic::mpsc::Sender<(#spawn_enum, rtic::slab::SlabHandle), #capacity_lit>);
-        let rq_recv_ty = quote!(std::sync::Mutex<rtic::mpsc::Receiver<(#spawn_enum, rtic::slab::SlabHandle), #capacity_lit>>);
+        // `None` requests the dispatcher to stop. Release time is only set for immediate spawns
+        // when statistics are collected.
+        let rq_send_ty = quote!(rtic::mpsc::Sender<Option<(#spawn_enum, rtic::slab::SlabHandle, Option<std::time::Instant>)>, #capacity_lit>);
+        let rq_recv_ty = quote!(std::sync::Mutex<rtic::mpsc::Receiver<Option<(#spawn_enum, rtic::slab::SlabHandle, Option<std::time::Instant>)>, #capacity_lit>>);
         let rq_expr = quote!({
             let (tx, rx) = rtic::mpsc::FutexQueue::new();
             (tx, std::sync::Mutex::new(rx))
@@ -70,7 +78,15 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
             }
         ));
 
+        let progress = util::progress_ident(level);
+        stmts.push(quote!(
+            #[doc(hidden)]
+            #[allow(non_upper_case_globals)]
+            static #progress: rtic::runtime::Progress = rtic::runtime::Progress::new();
+        ));
+
         // Generate match arms for each task
+        let mut stats = vec![];
         let arms = channel
             .tasks
             .iter()
@@ -80,29 +96,80 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
                 let input_queue = util::task_input_queue_ident(name);
                 let (_, tupled, pats, _) = util::regroup_inputs(&task.inputs);
                 let span_name = format!("task_{}", name);
+                let task_stats = util::task_stats_ident(name);
+                let task_name = name.to_string();
+                let spawn_name = format!("spawn {}", name);
+                stats.push(quote!(
+                    #(#cfgs)*
+                    #[doc(hidden)]
+                    #[allow(non_upper_case_globals)]
+                    static #task_stats: rtic::stats::TaskStats = rtic::stats::TaskStats::new(#task_name, #level);
+                ));
+
+                let task_index = util::task_index(app, name);
+                let run = quote!(
+                    rtic::trace::begin(#task_name);
+                    rtic::trace::flow_end(#spawn_name, flow);
+
+                    let _span = rtic::__profiling_span!(#span_name);
+
+                    rtic::__profiling_trace!("running");
+
+                    let timer = #task_stats.start(released_at);
+
+                    #name(
+                        #name::Context::new(&core::marker::PhantomData)
+                        #(,#pats)*
+                    );
+
+                    #task_stats.finish(timer);
+                    rtic::trace::end(#task_name);
+                );
+
+                let run = match extra.deadlines.get(name) {
+                    Some(deadline) => {
+                        let task_deadline = util::task_deadline_ident(name);
+                        let nanos = deadline.nanos;
+                        let skip = deadline.skip;
+                        stats.push(quote!(
+                            #(#cfgs)*
+                            #[doc(hidden)]
+                            #[allow(non_upper_case_globals)]
+                            static #task_deadline: rtic::deadline::TaskDeadline = rtic::deadline::TaskDeadline::new(
+                                #task_name,
+                                #level,
+                                std::time::Duration::from_nanos(#nanos),
+                                #skip,
+                            );
+                        ));
+
+                        // Inputs of a skipped instance are dropped
+                        quote!(
+                            if #task_deadline.start(released_at, #overrun_handler) {
+                                #run
+                                #task_deadline.finish(released_at, #overrun_handler);
+                            }
+                        )
+                    }
+                    None => run,
+                };
 
                 quote!(
                     #(#cfgs)*
                     #spawn_enum::#name => {
                         unsafe {
+                            let flow = rtic::trace::task_flow(#task_index, handle.index());
                             let #tupled = #input_queue.1.get_mut_unchecked().remove(handle);
 
-                            #[cfg(feature = "profiling")]
-                            let _span = rtic::tracing::span!(rtic::tracing::Level::TRACE, #span_name).entered();
-
-                            #[cfg(feature = "profiling")]
-                            rtic::tracing::trace!("running");
-
-                            #name(
-                                #name::Context::new(&core::marker::PhantomData)
-                                #(,#pats)*
-                            )
+                            #run
                         }
                     }
                 )
             })
             .collect::<Vec<_>>();
 
+        stmts.extend(stats);
+
         let doc = format!("Thread function to dispatch tasks at priority {}", level);
         let thread_ident = util::thread_ident(level);
         stmts.push(quote!(
@@ -113,23 +180,43 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
                 const PRIORITY: u8 = #level;
 
                 rtic::init_thread_state(PRIORITY);
+                #prefault_stack
 
-                #[cfg(feature = "profiling")]
-                rtic::tracing::trace!("thread {} waiting for init barrier", stringify!(#thread_ident));
+                // Cleared when the thread exits, i.e. by a panic in a task
+                let _alive = #progress.alive();
 
-                // Wait here until all threads have their priority set
-                #thread_init_barrier.wait();
+                rtic::__profiling_trace!("thread {} waiting for init barrier", stringify!(#thread_ident));
+
+                // Wait here until all threads have their priority set. The startup is aborted if
+                // another thread fails to spawn.
+                if !#thread_init_barrier.wait() {
+                    return;
+                }
 
-                #[cfg(feature = "profiling")]
-                rtic::tracing::trace!("thread {} running", stringify!(#thread_ident));
+                rtic::__profiling_trace!("thread {} running", stringify!(#thread_ident));
 
                 let mut rx = #rq.1.lock().unwrap();
-                while let item = rx.recv() {
-                    let (task, handle) = item.into_value();
+                loop {
+                    let item = rx.recv();
+                    // Scheduled tasks are not counted as released
+                    let released = item.instant().is_none();
+                    let scheduled_at = item.instant();
+                    let (task, handle, released_at) = match item.into_value() {
+                        Some(task) => task,
+                        None => break,
+                    };
+                    let released_at = scheduled_at.or(released_at);
+
+                    #progress.start(released, task as u32);
+
                     match task {
                         #(#arms)*,
                     }
+
+                    #progress.finish();
                 }
+
+                rtic::__profiling_trace!("thread {} stopped", stringify!(#thread_ident));
             }
         ));
     }
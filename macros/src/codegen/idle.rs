@@ -2,12 +2,16 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use rtic_syntax::{analyze::Analysis, ast::App, Context};
 
-use crate::codegen::{local_resources_struct, module, shared_resources_struct};
+use crate::{
+    codegen::{local_resources_struct, module, shared_resources_struct},
+    syntax::Extra,
+};
 
 /// Generates support code for `#[idle]` functions
 pub fn codegen(
     app: &App,
     analysis: &Analysis,
+    extra: &Extra,
 ) -> (
     // all generated idle definitions
     Vec<TokenStream>,
@@ -22,7 +26,8 @@ pub fn codegen(
         let name = &idle.name;
 
         if !idle.args.shared_resources.is_empty() {
-            let item = shared_resources_struct::codegen(Context::Idle, &mut shared_needs_lt, app);
+            let item =
+                shared_resources_struct::codegen(Context::Idle, &mut shared_needs_lt, app, extra);
             defs.push(item);
         }
 
@@ -37,6 +42,7 @@ pub fn codegen(
             local_needs_lt,
             app,
             analysis,
+            extra,
         ));
 
         let attrs = &idle.attrs;
@@ -62,15 +68,10 @@ pub fn codegen(
         (
             vec![],
             quote!(
-                let thread = std::thread::current();
-                rtic::ctrlc::set_handler(move || {
-                    thread.unpark();
-                }).expect("Failed to set ctrl-c handler");
-
-                std::thread::park();
-
-                // Fixes newline in terminal
-                println!("");
+                // Tasks run until the process is stopped, see `rtic::runtime::exit_on_signal`
+                loop {
+                    std::thread::park();
+                }
             ),
         )
     }
@@ -2,7 +2,10 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use rtic_syntax::{analyze::Analysis, ast::App, Context};
 
-use crate::codegen::{local_resources_struct, module};
+use crate::{
+    codegen::{local_resources_struct, module},
+    syntax::Extra,
+};
 
 type CodegenResult = (
     // all generated init definitions
@@ -12,7 +15,7 @@ type CodegenResult = (
 );
 
 /// Generates support code for `#[init]` functions
-pub fn codegen(app: &App, analysis: &Analysis) -> CodegenResult {
+pub fn codegen(app: &App, analysis: &Analysis, extra: &Extra) -> CodegenResult {
     let init = &app.init;
     let mut local_needs_lt = false;
     let name = &init.name;
@@ -62,12 +65,15 @@ pub fn codegen(app: &App, analysis: &Analysis) -> CodegenResult {
 
     // let locals_pat = locals_pat.iter();
 
-    let user_init_return = quote! {#shared, #local, #name::Monotonics};
+    let user_init_return = match &extra.init_result {
+        Some(ty) => quote!(#ty),
+        None => quote!((#shared, #local, #name::Monotonics)),
+    };
 
     defs.push(quote!(
         #(#attrs)*
         #[allow(non_snake_case)]
-        fn #name(#context: #name::Context) -> (#user_init_return) {
+        fn #name(#context: #name::Context) -> #user_init_return {
             #(#stmts)*
         }
     ));
@@ -85,11 +91,22 @@ pub fn codegen(app: &App, analysis: &Analysis) -> CodegenResult {
         local_needs_lt,
         app,
         analysis,
+        extra,
     ));
 
     // let locals_new = locals_new.iter();
-    let call_init = quote! {
-        let (shared_resources, local_resources, mut monotonics) = #name(#name::Context::new());
+    let config = extra.config.as_ref().map(|_| quote!(config));
+    let call_init = if extra.init_result.is_some() {
+        // Resources are not initialized and no dispatcher is spawned yet, `run` and `start` stop
+        // the background threads and return the error
+        quote! {
+            let (shared_resources, local_resources, mut monotonics) =
+                #name(#name::Context::new(#config))?;
+        }
+    } else {
+        quote! {
+            let (shared_resources, local_resources, mut monotonics) = #name(#name::Context::new(#config));
+        }
     };
 
     (defs, call_init)
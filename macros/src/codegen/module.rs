@@ -1,4 +1,4 @@
-use crate::codegen::util;
+use crate::{codegen::util, syntax::Extra};
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use rtic_syntax::{analyze::Analysis, ast::App, Context};
@@ -9,6 +9,7 @@ pub fn codegen(
     local_resources_tick: bool,
     app: &App,
     _analysis: &Analysis,
+    extra: &Extra,
 ) -> TokenStream2 {
     let mut items = vec![];
     let mut module_items = vec![];
@@ -87,6 +88,15 @@ pub fn codegen(
         values.push(quote!(shared: #name::SharedResources::new(#marker)));
     }
 
+    if let (Context::Init, Some(ty)) = (ctxt, &extra.config) {
+        fields.push(quote!(
+            /// Configuration passed to `run` or `start`
+            pub config: #ty
+        ));
+
+        values.push(quote!(config));
+    }
+
     if let Context::Init = ctxt {
         let monotonic_types: Vec<_> = app
             .monotonics
@@ -134,7 +144,7 @@ pub fn codegen(
     };
 
     let marker = if ctxt.is_init() {
-        None
+        extra.config.as_ref().map(|ty| quote!(config: #ty))
     } else {
         Some(quote!(marker: &#lt core::marker::PhantomData<()>))
     };
@@ -166,6 +176,33 @@ pub fn codegen(
         pub use super::#internal_context_name as Context;
     ));
 
+    if let Context::Idle = ctxt {
+        let shutdown_requested = util::shutdown_requested_ident();
+        let idle_stop = util::idle_stop_ident();
+        items.push(quote!(
+            impl<#lt> #internal_context_name<#lt> {
+                /// Returns `true` once the application is requested to stop, i.e. by Ctrl-C or
+                /// SIGTERM. Once idle has checked it, the process does not exit before idle calls
+                /// [`shutdown_done`](Self::shutdown_done) or `rtic::runtime::IDLE_STOP_TIMEOUT`
+                /// has passed.
+                #[inline(always)]
+                pub fn shutdown_requested(&self) -> bool {
+                    #idle_stop.watch();
+                    #shutdown_requested.load(std::sync::atomic::Ordering::Relaxed)
+                }
+
+                /// Acknowledges the shutdown request once idle has finished its cleanup and
+                /// parks the thread until the process exits
+                pub fn shutdown_done(&self) -> ! {
+                    #idle_stop.done();
+                    loop {
+                        std::thread::park();
+                    }
+                }
+            }
+        ));
+    }
+
     if let Context::SoftwareTask(..) = ctxt {
         let spawnee = &app.software_tasks[name];
         let priority = spawnee.args.priority;
@@ -176,9 +213,24 @@ pub fn codegen(
         let (inputs_args, inputs_tupled, inputs_untupled, inputs_ty) =
             util::regroup_inputs(&spawnee.inputs);
         let run_queue = util::run_queue_ident(priority);
+        let progress = util::progress_ident(priority);
         let input_queue = util::task_input_queue_ident(name);
 
         let internal_spawn_ident = util::internal_task_spawn_ident(name);
+        let task_index = util::task_index(app, name);
+        let spawn_name = format!("spawn {}", name);
+        // Flow from the spawning slice to the task execution
+        let trace_spawn = quote!(
+            rtic::trace::begin(#spawn_name);
+            rtic::trace::flow_begin(#spawn_name, rtic::trace::task_flow(#task_index, handle.index()));
+            rtic::trace::end(#spawn_name);
+        );
+        // Deadlines are counted from release, so it is always recorded for them
+        let release_time = if extra.deadlines.contains_key(name) {
+            quote!(Some(std::time::Instant::now()))
+        } else {
+            quote!(rtic::stats::release_time())
+        };
 
         // Spawn caller
         items.push(quote!(
@@ -189,13 +241,14 @@ pub fn codegen(
 
                 match #input_queue.0.insert(input) {
                     Ok(handle) => {
-                        #[cfg(feature = "profiling")]
-                        rtic::tracing::trace!("spawn {}", stringify!(#name));
+                        rtic::__profiling_trace!("spawn {}", stringify!(#name));
+                        #trace_spawn
 
                         // Should never fail if capacity calculations are correct
-                        if #run_queue.0.send((#spawn_enum::#name, handle)).is_err() {
+                        if #run_queue.0.send(Some((#spawn_enum::#name, handle, #release_time))).is_err() {
                             panic!("Run queue full!");
                         }
+                        #progress.release();
 
                         Ok(())
                     },
@@ -215,13 +268,14 @@ pub fn codegen(
 
                 match #input_queue.0.insert(input) {
                     Ok(handle) => {
-                        #[cfg(feature = "profiling")]
-                        rtic::tracing::trace!("schedule {} at {:?}", stringify!(#name), instant);
+                        rtic::__profiling_trace!("schedule {} at {:?}", stringify!(#name), instant);
+                        #trace_spawn
 
                         // Should never fail if capacity calculations are correct
-                        if #run_queue.0.send_scheduled((#spawn_enum::#name, handle), instant).is_err() {
+                        if #run_queue.0.send_scheduled(Some((#spawn_enum::#name, handle, None)), instant).is_err() {
                             panic!("Schedule queue full!");
                         }
+                        #progress.schedule();
 
                         Ok(())
                     },
@@ -239,8 +293,7 @@ pub fn codegen(
             pub fn #internal_spawn_after_ident(dur: std::time::Duration, #(#inputs_args,)*) -> Result<(), #inputs_ty> {
                 let instant = std::time::Instant::now() + dur;
 
-                #[cfg(feature = "profiling")]
-                rtic::tracing::trace!("schedule {} after {:?}", stringify!(#name), dur);
+                rtic::__profiling_trace!("schedule {} after {:?}", stringify!(#name), dur);
 
                 #internal_spawn_at_ident(instant #(,#inputs_untupled)*)
             }
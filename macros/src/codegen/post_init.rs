@@ -5,10 +5,10 @@ use rtic_syntax::{
     ast::App,
 };
 
-use crate::codegen::util;
+use crate::{codegen::util, syntax::Extra};
 
 /// Generates code that runs after `#[init]` returns
-pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
+pub fn codegen(app: &App, analysis: &Analysis, extra: &Extra) -> Vec<TokenStream> {
     let mut stmts = vec![];
 
     // Initialize all lazy_static queues
@@ -24,14 +24,32 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
         let mangled_name = util::static_shared_resource_ident(name);
         // If it's live
         let cfgs = res.cfgs.clone();
-        if analysis.shared_resource_locations.get(name).is_some() {
-            let ceiling = match analysis.ownerships.get(name) {
+        if analysis.shared_resources.contains(name) && extra.atomic_resources.contains(name) {
+            stmts.push(quote!(
+                #(#cfgs)*
+                // Atomic resources are written directly without a mutex
+                #mangled_name.get_mut_unchecked().as_mut_ptr().write(shared_resources.#name);
+            ));
+        } else if analysis.shared_resources.contains(name) {
+            let mut ceiling = match analysis.ownerships.get(name) {
                 Some(Ownership::Owned { priority }) => *priority,
                 Some(Ownership::CoOwned { priority }) => *priority,
                 Some(Ownership::Contended { ceiling }) => *ceiling,
                 None => 0,
             };
 
+            // Analysis assumes that idle runs at priority 0. Resources shared with tasks already
+            // have a ceiling above `idle_priority` (see `check::app`), but a resource that only
+            // idle uses is owned at priority 0 and would get a ceiling below the idle thread.
+            let used_by_idle = app
+                .idle
+                .as_ref()
+                .map(|idle| idle.args.shared_resources.contains_key(name))
+                .unwrap_or(false);
+            if used_by_idle {
+                ceiling = ceiling.max(extra.idle_priority);
+            }
+
             stmts.push(quote!(
                 // We include the cfgs
                 #(#cfgs)*
@@ -49,7 +67,7 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
         let mangled_name = util::static_local_resource_ident(name);
         // If it's live
         let cfgs = res.cfgs.clone();
-        if analysis.local_resource_locations.get(name).is_some() {
+        if analysis.local_resources.contains(name) {
             stmts.push(quote!(
                 // We include the cfgs
                 #(#cfgs)*
@@ -1,13 +1,15 @@
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{quote, quote_spanned};
 use rtic_syntax::{analyze::Analysis, ast::App};
+use syn::spanned::Spanned;
 
-use crate::codegen::util;
+use crate::{codegen::util, syntax::Extra};
 
 /// Generates `static` variables and shared resource proxies
 pub fn codegen(
     app: &App,
     _analysis: &Analysis,
+    extra: &Extra,
 ) -> (
     // mod_app -- the `static` variables behind the proxies
     Vec<TokenStream>,
@@ -22,6 +24,14 @@ pub fn codegen(
         let ty = &res.ty;
         let mangled_name = &util::static_shared_resource_ident(&name);
         let attrs = &res.attrs;
+        let is_atomic = extra.atomic_resources.contains(name);
+
+        // Atomic resources are stored as is, everything else is protected by a mutex
+        let storage_ty = if is_atomic {
+            quote!(#ty)
+        } else {
+            quote!(rtic::PcpMutex<#ty>)
+        };
 
         // For future use
         // let doc = format!(" RTIC internal: {}:{}", file!(), line!());
@@ -32,14 +42,22 @@ pub fn codegen(
             #[doc(hidden)]
             #(#attrs)*
             #(#cfgs)*
-            static #mangled_name: rtic::RacyCell<core::mem::MaybeUninit<rtic::PcpMutex<#ty>>>
+            static #mangled_name: rtic::RacyCell<core::mem::MaybeUninit<#storage_ty>>
              = rtic::RacyCell::new(core::mem::MaybeUninit::uninit());
         ));
 
         // For future use
         // let doc = format!(" RTIC internal: {}:{}", file!(), line!());
 
-        if !res.properties.lock_free {
+        if is_atomic {
+            // Atomic resources are accessed concurrently from multiple threads
+            mod_app.push(quote_spanned!(ty.span()=>
+                #(#cfgs)*
+                const _: () = rtic::assert_sync::<#ty>();
+            ));
+        }
+
+        if !res.properties.lock_free && !is_atomic {
             mod_resources.push(quote!(
                 // #[doc = #doc]
                 #[doc(hidden)]
@@ -65,6 +83,23 @@ pub fn codegen(
 
             let tracing_name = format!("shared_{}", name);
             let tracing_name_locked = format!("shared_{}_locked", name);
+            let lock_stats = util::lock_stats_ident(name);
+            let lock_flow = util::lock_flow_ident(name);
+            let resource_name = name.to_string();
+            mod_app.push(quote!(
+                #(#cfgs)*
+                #[doc(hidden)]
+                #[allow(non_upper_case_globals)]
+                static #lock_stats: rtic::stats::LockStats = rtic::stats::LockStats::new(#resource_name);
+
+                #(#cfgs)*
+                #[doc(hidden)]
+                #[allow(non_upper_case_globals)]
+                static #lock_flow: rtic::trace::LockFlow = rtic::trace::LockFlow::new();
+            ));
+
+            let trace_wait = format!("wait {}", name);
+            let trace_locked = format!("lock {}", name);
 
             mod_app.push(quote!(
                 #(#cfgs)*
@@ -75,30 +110,37 @@ pub fn codegen(
                     fn lock<RTIC_INTERNAL_R>(&mut self, f: impl FnOnce(&mut #ty) -> RTIC_INTERNAL_R) -> RTIC_INTERNAL_R {
                         let mutex = unsafe { & *#ptr };
 
-                        #[cfg(feature = "profiling")]
-                        let _span = rtic::tracing::span!(rtic::tracing::Level::TRACE, #tracing_name).entered();
+                        let _span = rtic::__profiling_span!(#tracing_name);
+
+                        rtic::__profiling_trace!("locking");
 
-                        #[cfg(feature = "profiling")]
-                        rtic::tracing::trace!("locking");
+                        let requested_at = #lock_stats.request();
+                        rtic::trace::begin(#trace_wait);
+                        #lock_flow.wait();
 
                         let r = mutex.lock(|res| {
-                            #[cfg(feature = "profiling")]
-                            let _span = rtic::tracing::span!(rtic::tracing::Level::TRACE, #tracing_name_locked).entered();
+                            let acquired_at = #lock_stats.acquired(requested_at);
+                            rtic::trace::end(#trace_wait);
+                            rtic::trace::begin(#trace_locked);
+                            // Flow from the slice of the previous holder, if it saw this task waiting
+                            #lock_flow.acquired(#trace_locked);
+
+                            let _span = rtic::__profiling_span!(#tracing_name_locked);
 
-                            #[cfg(feature = "profiling")]
-                            rtic::tracing::trace!("locked");
+                            rtic::__profiling_trace!("locked");
 
                             // Execute user closure with the resource reference
                             let r = f(res);
 
-                            #[cfg(feature = "profiling")]
-                            rtic::tracing::trace!("unlocking");
+                            rtic::__profiling_trace!("unlocking");
 
+                            #lock_flow.release(#trace_locked);
+                            rtic::trace::end(#trace_locked);
+                            #lock_stats.release(acquired_at);
                             r
                         });
 
-                        #[cfg(feature = "profiling")]
-                        rtic::tracing::trace!("unlocked");
+                        rtic::__profiling_trace!("unlocked");
 
                         r
                     }
@@ -2,10 +2,10 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use rtic_syntax::{ast::App, Context};
 
-use crate::codegen::util;
+use crate::{codegen::util, syntax::Extra};
 
 /// Generate shared resources structs
-pub fn codegen(ctxt: Context, needs_lt: &mut bool, app: &App) -> TokenStream {
+pub fn codegen(ctxt: Context, needs_lt: &mut bool, app: &App, extra: &Extra) -> TokenStream {
     let mut lt = None;
 
     let resources = match ctxt {
@@ -34,7 +34,27 @@ pub fn codegen(ctxt: Context, needs_lt: &mut bool, app: &App) -> TokenStream {
         let ty = &res.ty;
         let mangled_name = util::static_shared_resource_ident(&name);
 
-        if !res.properties.lock_free {
+        if extra.atomic_resources.contains(name) {
+            // Atomic resources are always accessed through a shared reference
+            let lt = if ctxt.runs_once() {
+                quote!('static)
+            } else {
+                lt = Some(quote!('a));
+                quote!('a)
+            };
+
+            fields.push(quote!(
+                #(#cfgs)*
+                pub #name: &#lt #ty
+            ));
+
+            values.push(quote!(
+                #(#cfgs)*
+                #name: &*#mangled_name.get_unchecked().as_ptr()
+            ));
+
+            continue;
+        } else if !res.properties.lock_free {
             if access.is_shared() {
                 lt = Some(quote!('a));
 
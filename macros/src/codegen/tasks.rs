@@ -2,9 +2,12 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use rtic_syntax::{analyze::Analysis, ast::App, Context};
 
-use crate::codegen::{local_resources_struct, module, shared_resources_struct, util};
+use crate::{
+    codegen::{local_resources_struct, module, shared_resources_struct, util},
+    syntax::Extra,
+};
 
-pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
+pub fn codegen(app: &App, analysis: &Analysis, extra: &Extra) -> Vec<TokenStream> {
     let mut stmts = vec![];
 
     for (name, task) in &app.software_tasks {
@@ -67,6 +70,7 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
                 Context::SoftwareTask(name),
                 &mut shared_needs_lt,
                 app,
+                extra,
             );
 
             stmts.push(item);
@@ -79,6 +83,7 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream> {
             local_needs_lt,
             app,
             analysis,
+            extra,
         ));
     }
 
@@ -30,9 +30,102 @@ pub fn thread_ident(priority: u8) -> Ident {
     mark_internal_name(&format!("thread_P{}", priority))
 }
 
-/// Generates an OS thread name
-pub fn thread_name(priority: u8) -> String {
-    format!("thd_P{}", priority)
+/// Generates an OS thread name, i.e. `app-P2`
+pub fn thread_name(app: &Ident, priority: u8) -> String {
+    app_thread_name(app, &format!("-P{}", priority))
+}
+
+/// Generates an OS thread name from the app name and a suffix, i.e. `app-idle`
+///
+/// Linux limits thread names to 15 bytes, so the app name is shortened to keep the suffix.
+pub fn app_thread_name(app: &Ident, suffix: &str) -> String {
+    const MAX_LEN: usize = 15;
+
+    let app = app.to_string();
+    let mut len = app.len().min(MAX_LEN - suffix.len());
+    while !app.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    format!("{}{}", &app[..len], suffix)
+}
+
+/// Returns a number identifying a software task in traces
+pub fn task_index(app: &App, task: &Ident) -> u32 {
+    app.software_tasks
+        .keys()
+        .position(|name| name == task)
+        .expect("unknown task") as u32
+}
+
+/// Generates an identifier for the `rtic::deadline::TaskDeadline` of a task
+pub fn task_deadline_ident(task: &Ident) -> Ident {
+    mark_internal_name(&format!("{}_deadline", task))
+}
+
+/// Generates an identifier for the `rtic::stats::LockStats` of a shared resource
+pub fn lock_stats_ident(resource: &Ident) -> Ident {
+    mark_internal_name(&format!("{}_lock_stats", resource))
+}
+
+/// Generates an identifier for the `rtic::trace::LockFlow` of a shared resource
+pub fn lock_flow_ident(resource: &Ident) -> Ident {
+    mark_internal_name(&format!("{}_lock_flow", resource))
+}
+
+/// Generates an identifier for the `rtic::stats::TaskStats` of a task
+pub fn task_stats_ident(task: &Ident) -> Ident {
+    mark_internal_name(&format!("{}_stats", task))
+}
+
+/// Generates an identifier for the `rtic::runtime::Progress` of a dispatcher
+pub fn progress_ident(priority: u8) -> Ident {
+    mark_internal_name(&format!("P{}_progress", priority))
+}
+
+/// Generates an identifier for the flag that is set once the app is started
+pub fn started_ident() -> Ident {
+    mark_internal_name("started")
+}
+
+/// Generates an identifier for the flag that is set once shutdown is requested
+pub fn shutdown_requested_ident() -> Ident {
+    mark_internal_name("shutdown_requested")
+}
+
+/// Generates an identifier for the acknowledgement of a shutdown request by idle
+pub fn idle_stop_ident() -> Ident {
+    mark_internal_name("idle_stop")
+}
+
+/// Generates an identifier for the function that stops dispatcher threads
+pub fn request_shutdown_ident() -> Ident {
+    mark_internal_name("request_shutdown")
+}
+
+/// Generates an identifier for the function that collects `rtic::runtime::Stats`
+pub fn stats_ident() -> Ident {
+    mark_internal_name("stats")
+}
+
+/// Generates an identifier for the function that runs `#[init]` and initializes resources
+pub fn init_ident() -> Ident {
+    mark_internal_name("init")
+}
+
+/// Generates an identifier for the wrapper of the `#[config]` function
+pub fn config_hook_ident() -> Ident {
+    mark_internal_name("config")
+}
+
+/// Generates an identifier for the wrapper of the `#[profiling]` function
+pub fn profiling_hook_ident() -> Ident {
+    mark_internal_name("profiling")
+}
+
+/// Generates an identifier for the function that spawns dispatcher threads
+pub fn spawn_threads_ident() -> Ident {
+    mark_internal_name("spawn_threads")
 }
 
 /// Generates an identifier for the `enum` of `spawn`-able tasks
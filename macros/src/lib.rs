@@ -2,7 +2,9 @@ use proc_macro::TokenStream;
 use rtic_syntax::Settings;
 use std::{fs, path::Path};
 
+mod check;
 mod codegen;
+mod syntax;
 
 /// Attribute used to declare a RTIC application
 ///
@@ -14,12 +16,21 @@ pub fn app(args: TokenStream, input: TokenStream) -> TokenStream {
     settings.parse_binds = true;
     settings.parse_extern_interrupt = true;
 
-    let (app, analysis) = match rtic_syntax::parse(args, input, settings) {
+    let (args, input, extra) = match syntax::parse(args.into(), input.into()) {
         Err(e) => return e.to_compile_error().into(),
         Ok(x) => x,
     };
 
-    let ts = codegen::app(&app, &analysis);
+    let (app, analysis) = match rtic_syntax::parse2(args, input, settings) {
+        Err(e) => return e.to_compile_error().into(),
+        Ok(x) => x,
+    };
+
+    if let Err(e) = check::app(&app, &analysis, &extra) {
+        return e.to_compile_error().into();
+    }
+
+    let ts = codegen::app(&app, &analysis, &extra);
 
     // Try to write the expanded code to disk
     if Path::new("target").exists() {
@@ -0,0 +1,728 @@
+//! linux-rtic specific syntax that is not understood by `rtic-syntax`
+//!
+//! The extensions are parsed and stripped from the input before it is handed over to
+//! `rtic_syntax::parse2`, which would otherwise reject them.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    mem,
+    net::SocketAddr,
+};
+
+use proc_macro2::{Delimiter, Group, Span, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::{
+    parse::{Parse, ParseStream, Parser},
+    punctuated::Punctuated,
+    spanned::Spanned,
+    AttrStyle, Attribute, Error, Expr, ExprLit, Fields, GenericArgument, Ident, Item, ItemFn,
+    ItemMod, Lit, LitStr, PathArguments, Result, ReturnType, Token, Type, TypePath,
+};
+
+/// Highest `SCHED_FIFO` priority
+const MAX_PRIORITY: u8 = 99;
+
+/// Information collected from linux-rtic specific syntax
+#[derive(Default)]
+pub struct Extra {
+    /// Shared resources which are accessed directly (without a lock) by tasks of any priority
+    pub atomic_resources: HashSet<Ident>,
+    /// Priority of the main thread while `#[init]` runs. Defaults to the highest task priority.
+    pub init_priority: Option<u8>,
+    /// Priority of the main thread while `#[idle]` runs. Zero means `SCHED_OTHER`.
+    pub idle_priority: u8,
+    /// Variant of `rtic::environment::Policy` used for the startup audit
+    pub audit_policy: Option<Ident>,
+    /// Lock all memory of the process with `mlockall`
+    pub mlock: bool,
+    /// Number of bytes of each thread's stack to fault in before tasks run
+    pub prefault_stack: Option<usize>,
+    /// Number of bytes of heap to fault in before `#[init]`
+    pub prefault_heap: Option<usize>,
+    /// Stack size of dispatcher threads. `None` uses the `std` default.
+    pub stack_size: Option<usize>,
+    /// Stack sizes of dispatcher threads at specific priorities, overriding `stack_size`
+    pub stack_sizes: BTreeMap<u8, (Span, usize)>,
+    /// Do not generate `fn main`, the app is started with `app::start` instead
+    pub library: bool,
+    /// Type of the configuration passed to `#[init]`
+    pub config: Option<Type>,
+    /// Function marked with `#[config]`, which creates the configuration in the generated `main`
+    pub config_hook: Option<Ident>,
+    /// Return type of a fallible `#[init]`, i.e. `Result<(Shared, Local, init::Monotonics), E>`
+    pub init_result: Option<Type>,
+    /// Error type `E` of a fallible `#[init]`, returned by `app::run` and `app::start`
+    pub init_error: Option<Type>,
+    /// Time after which a dispatcher is considered stalled, in nanoseconds
+    pub watchdog: Option<u64>,
+    /// Function marked with `#[watchdog]`, which handles stalls
+    pub watchdog_hook: Option<Ident>,
+    /// Unix socket path or localhost `ip:port` to serve metrics on
+    pub metrics: Option<LitStr>,
+    /// Relative deadlines of software tasks
+    pub deadlines: HashMap<Ident, Deadline>,
+    /// Function marked with `#[overrun]`, which handles deadline misses
+    pub overrun_hook: Option<Ident>,
+    /// Function marked with `#[profiling]`, which sets up the subscriber in the generated `main`
+    pub profiling_hook: Option<Ident>,
+}
+
+/// Deadline of a software task, set with `#[task(deadline = "10ms")]`
+pub struct Deadline {
+    /// Time from release until the task must have completed, in nanoseconds
+    pub nanos: u64,
+    /// Skip instances whose deadline has passed before they started, `overrun = "skip"`
+    pub skip: bool,
+}
+
+impl Extra {
+    /// Returns stack size of the dispatcher thread at `priority`
+    pub fn stack_size(&self, priority: u8) -> Option<usize> {
+        self.stack_sizes
+            .get(&priority)
+            .map(|&(_, size)| size)
+            .or(self.stack_size)
+    }
+}
+
+/// `name = value` argument of the `#[app]` attribute
+struct AppArg {
+    name: Ident,
+    eq_token: Token![=],
+    value: Expr,
+}
+
+impl Parse for AppArg {
+    fn parse(input: ParseStream) -> Result<Self> {
+        Ok(AppArg {
+            name: input.parse()?,
+            eq_token: input.parse()?,
+            value: input.parse()?,
+        })
+    }
+}
+
+impl ToTokens for AppArg {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        self.name.to_tokens(tokens);
+        self.eq_token.to_tokens(tokens);
+        self.value.to_tokens(tokens);
+    }
+}
+
+/// Extracts linux-rtic specific syntax
+///
+/// Returns `#[app]` arguments and module that can be passed to `rtic-syntax`.
+pub fn parse(args: TokenStream, input: TokenStream) -> Result<(TokenStream, TokenStream, Extra)> {
+    let mut extra = Extra::default();
+    let args = parse_args(args, &mut extra)?;
+    let mut module: ItemMod = syn::parse2(input)?;
+
+    if let Some((_, items)) = &mut module.content {
+        for item in items {
+            match item {
+                Item::Struct(item) if item.attrs.iter().any(|attr| attr_eq(attr, "shared")) => {
+                    parse_shared_struct(&mut item.fields, &mut extra)?;
+                }
+                Item::Fn(item) => {
+                    parse_hooks(item, &mut extra)?;
+                    parse_task_args(item, &mut extra)?;
+                    parse_init(item, &mut extra)?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let (Some(hook), None) = (&extra.config_hook, &extra.config) {
+        return Err(Error::new(
+            hook.span(),
+            "`#[config]` requires the `config` argument of `#[app]`",
+        ));
+    }
+
+    if let (Some(hook), None) = (&extra.watchdog_hook, &extra.watchdog) {
+        return Err(Error::new(
+            hook.span(),
+            "`#[watchdog]` requires the `watchdog` argument of `#[app]`",
+        ));
+    }
+
+    if let (Some(hook), true) = (&extra.profiling_hook, extra.library) {
+        return Err(Error::new(
+            hook.span(),
+            "`#[profiling]` is not used with `main = false`, call `rtic::profiling::init` instead",
+        ));
+    }
+
+    if let (Some(hook), true) = (&extra.overrun_hook, extra.deadlines.is_empty()) {
+        return Err(Error::new(
+            hook.span(),
+            "`#[overrun]` requires a task with the `deadline` argument",
+        ));
+    }
+
+    Ok((args, module.into_token_stream(), extra))
+}
+
+/// Extracts linux-rtic specific `#[app]` arguments and forwards the rest
+fn parse_args(args: TokenStream, extra: &mut Extra) -> Result<TokenStream> {
+    let args = Punctuated::<AppArg, Token![,]>::parse_terminated.parse2(args)?;
+    let mut forwarded = Punctuated::<AppArg, Token![,]>::new();
+    let mut seen = HashSet::new();
+
+    for arg in args {
+        let name = arg.name.to_string();
+
+        match &*name {
+            "init_priority" => extra.init_priority = Some(parse_priority(&arg.value)?),
+            "idle_priority" => extra.idle_priority = parse_priority(&arg.value)?,
+            "audit" => extra.audit_policy = Some(parse_audit_policy(&arg.value)?),
+            "mlock" => extra.mlock = parse_bool(&arg.value)?,
+            "prefault_stack" => extra.prefault_stack = Some(parse_size(&arg.value)?),
+            "prefault_heap" => extra.prefault_heap = Some(parse_size(&arg.value)?),
+            "stack_size" => parse_stack_size(&arg.value, extra)?,
+            "main" => extra.library = !parse_bool(&arg.value)?,
+            "config" => extra.config = Some(parse_type(&arg.value)?),
+            "watchdog" => extra.watchdog = Some(parse_duration(&arg.value)?),
+            "metrics" => extra.metrics = Some(parse_metrics_endpoint(&arg.value)?),
+            // Duplicates are reported by `rtic-syntax`
+            _ => {
+                forwarded.push(arg);
+                continue;
+            }
+        }
+
+        if !seen.insert(name) {
+            return Err(Error::new(
+                arg.name.span(),
+                "argument appears more than once",
+            ));
+        }
+    }
+
+    Ok(forwarded.into_token_stream())
+}
+
+/// Parses thread priority in the range of `0..=99`
+fn parse_priority(value: &Expr) -> Result<u8> {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(lit), ..
+        }) => match lit.base10_parse::<u8>() {
+            Ok(priority) if priority <= MAX_PRIORITY => Ok(priority),
+            _ => Err(Error::new(
+                lit.span(),
+                format!("priority must be in the range of 0..={}", MAX_PRIORITY),
+            )),
+        },
+        _ => Err(Error::new_spanned(
+            value,
+            "unexpected argument value; this should be an integer",
+        )),
+    }
+}
+
+/// Parses a type path, i.e. `super::Config`
+fn parse_type(value: &Expr) -> Result<Type> {
+    match value {
+        Expr::Path(path) if path.attrs.is_empty() => Ok(Type::Path(TypePath {
+            qself: path.qself.clone(),
+            path: path.path.clone(),
+        })),
+        _ => Err(Error::new_spanned(
+            value,
+            "unexpected argument value; this should be a type path",
+        )),
+    }
+}
+
+/// Parses boolean literal
+fn parse_bool(value: &Expr) -> Result<bool> {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Bool(lit),
+            ..
+        }) => Ok(lit.value),
+        _ => Err(Error::new_spanned(
+            value,
+            "unexpected argument value; this should be a boolean",
+        )),
+    }
+}
+
+/// Parses size in bytes, either an integer or a string with `K`, `M` or `G` suffix, i.e. `"256K"`
+fn parse_size(value: &Expr) -> Result<usize> {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(lit), ..
+        }) => lit.base10_parse(),
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) => {
+            let size = lit.value();
+            let size = size.trim();
+            let (number, multiplier) = match size.char_indices().last() {
+                Some((i, 'K')) | Some((i, 'k')) => (&size[..i], 1 << 10),
+                Some((i, 'M')) | Some((i, 'm')) => (&size[..i], 1 << 20),
+                Some((i, 'G')) | Some((i, 'g')) => (&size[..i], 1 << 30),
+                _ => (size, 1),
+            };
+
+            number
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| number.checked_mul(multiplier))
+                .ok_or_else(|| {
+                    Error::new(
+                        lit.span(),
+                        "invalid size; expected a number of bytes with an optional `K`, `M` or `G` suffix",
+                    )
+                })
+        }
+        _ => Err(Error::new_spanned(
+            value,
+            "unexpected argument value; this should be an integer or a string",
+        )),
+    }
+}
+
+/// Parses duration in nanoseconds from a string with `s`, `ms`, `us` or `ns` suffix, i.e. `"100ms"`
+fn parse_duration(value: &Expr) -> Result<u64> {
+    let lit = match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) => lit,
+        _ => {
+            return Err(Error::new_spanned(
+                value,
+                "unexpected argument value; this should be a string",
+            ))
+        }
+    };
+
+    let duration = lit.value();
+    let duration = duration.trim();
+    let (number, multiplier) = [
+        ("ns", 1),
+        ("us", 1_000),
+        ("ms", 1_000_000),
+        ("s", 1_000_000_000),
+    ]
+    .iter()
+    .find_map(|&(suffix, multiplier)| {
+        duration
+            .strip_suffix(suffix)
+            .map(|number| (number, multiplier))
+    })
+    .unwrap_or((duration, 0));
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|&nanos| nanos > 0)
+        .ok_or_else(|| {
+            Error::new(
+                lit.span(),
+                "invalid duration; expected a positive number with `s`, `ms`, `us` or `ns` suffix",
+            )
+        })
+}
+
+/// Parses either a stack size for all dispatcher threads or a list of `(priority, size)` tuples
+fn parse_stack_size(value: &Expr, extra: &mut Extra) -> Result<()> {
+    let elems = match value {
+        Expr::Array(array) => &array.elems,
+        _ => {
+            extra.stack_size = Some(parse_size(value)?);
+            return Ok(());
+        }
+    };
+
+    for elem in elems {
+        match elem {
+            Expr::Tuple(tuple) if tuple.elems.len() == 2 => {
+                let priority = parse_priority(&tuple.elems[0])?;
+                let size = parse_size(&tuple.elems[1])?;
+                if extra
+                    .stack_sizes
+                    .insert(priority, (tuple.elems[0].span(), size))
+                    .is_some()
+                {
+                    return Err(Error::new_spanned(
+                        &tuple.elems[0],
+                        "priority appears more than once",
+                    ));
+                }
+            }
+            _ => {
+                return Err(Error::new_spanned(
+                    elem,
+                    "unexpected element; this should be a `(priority, size)` tuple",
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses an absolute Unix socket path or a localhost `ip:port`, same as `rtic::metrics::Endpoint`
+fn parse_metrics_endpoint(value: &Expr) -> Result<LitStr> {
+    let lit = match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) => lit,
+        _ => {
+            return Err(Error::new_spanned(
+                value,
+                "unexpected argument value; this should be a string",
+            ))
+        }
+    };
+
+    let endpoint = lit.value();
+    let valid = endpoint.starts_with('/')
+        || endpoint
+            .parse::<SocketAddr>()
+            .map_or(false, |addr| addr.ip().is_loopback());
+    if !valid {
+        return Err(Error::new(
+            lit.span(),
+            "metrics endpoint must be an absolute Unix socket path or a localhost address, i.e. \"127.0.0.1:9100\"",
+        ));
+    }
+
+    Ok(lit.clone())
+}
+
+/// Parses audit policy string into `rtic::environment::Policy` variant
+fn parse_audit_policy(value: &Expr) -> Result<Ident> {
+    if let Expr::Lit(ExprLit {
+        lit: Lit::Str(lit), ..
+    }) = value
+    {
+        let variant = match &*lit.value() {
+            "ignore" => "Ignore",
+            "warn" => "Warn",
+            "fail" => "Fail",
+            "degrade" => "Degrade",
+            _ => {
+                return Err(Error::new(
+                    lit.span(),
+                    "audit policy must be one of \"ignore\", \"warn\", \"fail\" or \"degrade\"",
+                ))
+            }
+        };
+
+        Ok(Ident::new(variant, lit.span()))
+    } else {
+        Err(Error::new_spanned(
+            value,
+            "unexpected argument value; this should be a string",
+        ))
+    }
+}
+
+/// Extracts functions marked with `#[config]`, `#[watchdog]`, `#[overrun]` or `#[profiling]`
+fn parse_hooks(item: &mut ItemFn, extra: &mut Extra) -> Result<()> {
+    if take_hook_attr(item, "config", &extra.config_hook)? {
+        if !item.sig.inputs.is_empty() || item.sig.asyncness.is_some() {
+            return Err(Error::new(
+                item.sig.ident.span(),
+                "`#[config]` function must have signature `fn() -> T`",
+            ));
+        }
+
+        extra.config_hook = Some(item.sig.ident.clone());
+    }
+
+    if take_hook_attr(item, "watchdog", &extra.watchdog_hook)? {
+        if item.sig.inputs.len() != 1 || item.sig.asyncness.is_some() {
+            return Err(Error::new(
+                item.sig.ident.span(),
+                "`#[watchdog]` function must have signature `fn(&rtic::watchdog::Stall)`",
+            ));
+        }
+
+        extra.watchdog_hook = Some(item.sig.ident.clone());
+    }
+
+    if take_hook_attr(item, "overrun", &extra.overrun_hook)? {
+        if item.sig.inputs.len() != 1 || item.sig.asyncness.is_some() {
+            return Err(Error::new(
+                item.sig.ident.span(),
+                "`#[overrun]` function must have signature `fn(&rtic::deadline::Overrun)`",
+            ));
+        }
+
+        extra.overrun_hook = Some(item.sig.ident.clone());
+    }
+
+    if take_hook_attr(item, "profiling", &extra.profiling_hook)? {
+        if !item.sig.inputs.is_empty() || item.sig.asyncness.is_some() {
+            return Err(Error::new(
+                item.sig.ident.span(),
+                "`#[profiling]` function must have signature `fn() -> rtic::profiling::Profiling`",
+            ));
+        }
+
+        extra.profiling_hook = Some(item.sig.ident.clone());
+    }
+
+    Ok(())
+}
+
+/// Extracts `deadline` and `overrun` arguments of `#[task]`
+///
+/// Other arguments, i.e. `local = [x: u32 = 0]`, are not expressions, so the arguments are split at
+/// top-level commas instead of being parsed.
+fn parse_task_args(item: &mut ItemFn, extra: &mut Extra) -> Result<()> {
+    let attr = match item.attrs.iter_mut().find(|attr| attr_eq(attr, "task")) {
+        Some(attr) => attr,
+        None => return Ok(()),
+    };
+
+    let group = match attr.tokens.clone().into_iter().next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => group,
+        // `rtic-syntax` reports malformed arguments
+        _ => return Ok(()),
+    };
+
+    let mut args: Vec<Vec<TokenTree>> = vec![vec![]];
+    for token in group.stream() {
+        match &token {
+            TokenTree::Punct(punct) if punct.as_char() == ',' => args.push(vec![]),
+            _ => args.last_mut().unwrap().push(token),
+        }
+    }
+
+    let mut forwarded = Punctuated::<TokenStream, Token![,]>::new();
+    let mut deadline = None;
+    let mut overrun = None;
+    for arg in args {
+        let name = match arg.as_slice() {
+            [TokenTree::Ident(name), TokenTree::Punct(eq), ..]
+                if eq.as_char() == '=' && (name == "deadline" || name == "overrun") =>
+            {
+                name.clone()
+            }
+            [] => continue,
+            _ => {
+                forwarded.push(arg.into_iter().collect());
+                continue;
+            }
+        };
+
+        let value: Expr = syn::parse2(arg[2..].iter().cloned().collect())?;
+        let slot = if name == "deadline" {
+            &mut deadline
+        } else {
+            &mut overrun
+        };
+        if slot.replace(value).is_some() {
+            return Err(Error::new(name.span(), "argument appears more than once"));
+        }
+    }
+
+    let skip = match &overrun {
+        Some(value) => parse_overrun_policy(value)?,
+        None => false,
+    };
+
+    match (deadline, overrun) {
+        (Some(deadline), _) => {
+            extra.deadlines.insert(
+                item.sig.ident.clone(),
+                Deadline {
+                    nanos: parse_duration(&deadline)?,
+                    skip,
+                },
+            );
+        }
+        (None, Some(overrun)) => {
+            return Err(Error::new_spanned(
+                overrun,
+                "`overrun` requires the `deadline` argument",
+            ))
+        }
+        (None, None) => return Ok(()),
+    }
+
+    let mut stream = TokenStream::new();
+    stream.extend([TokenTree::Group(Group::new(
+        Delimiter::Parenthesis,
+        forwarded.into_token_stream(),
+    ))]);
+    attr.tokens = stream;
+
+    Ok(())
+}
+
+/// Parses overrun policy, returns `true` if late instances are skipped
+fn parse_overrun_policy(value: &Expr) -> Result<bool> {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) => match &*lit.value() {
+            "run" => Ok(false),
+            "skip" => Ok(true),
+            _ => Err(Error::new(
+                lit.span(),
+                "overrun policy must be one of \"run\" or \"skip\"",
+            )),
+        },
+        _ => Err(Error::new_spanned(
+            value,
+            "unexpected argument value; this should be a string",
+        )),
+    }
+}
+
+/// Removes `#[name]` attribute from a function, returns `true` if it was present
+fn take_hook_attr(item: &mut ItemFn, name: &str, existing: &Option<Ident>) -> Result<bool> {
+    let pos = match item.attrs.iter().position(|attr| attr_eq(attr, name)) {
+        Some(pos) => pos,
+        None => return Ok(false),
+    };
+
+    let attr = item.attrs.remove(pos);
+    if !attr.tokens.is_empty() {
+        return Err(Error::new_spanned(
+            attr.tokens,
+            format!("`#[{}]` does not take any arguments", name),
+        ));
+    }
+
+    if existing.is_some() {
+        return Err(Error::new(
+            item.sig.ident.span(),
+            format!("only one function can be `#[{}]`", name),
+        ));
+    }
+
+    Ok(true)
+}
+
+/// Accepts `Result` as the return type of `#[init]`
+///
+/// `rtic-syntax` only accepts the tuple, so it is unwrapped and the original type is kept.
+fn parse_init(item: &mut ItemFn, extra: &mut Extra) -> Result<()> {
+    if !item.attrs.iter().any(|attr| attr_eq(attr, "init")) {
+        return Ok(());
+    }
+
+    let ty = match &mut item.sig.output {
+        ReturnType::Type(_, ty) => ty,
+        // `rtic-syntax` reports the missing return type
+        ReturnType::Default => return Ok(()),
+    };
+
+    let args = match &**ty {
+        Type::Path(TypePath { qself: None, path }) => match path.segments.last() {
+            Some(segment) if segment.ident == "Result" => match &segment.arguments {
+                PathArguments::AngleBracketed(args) => &args.args,
+                _ => return Ok(()),
+            },
+            _ => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+
+    let (resources, error) = match (args.first(), args.iter().nth(1)) {
+        (
+            Some(GenericArgument::Type(resources @ Type::Tuple(_))),
+            Some(GenericArgument::Type(error)),
+        ) => (resources.clone(), error.clone()),
+        (Some(GenericArgument::Type(Type::Tuple(_))), None) => {
+            return Err(Error::new_spanned(
+                &ty,
+                "the error type of `#[init]` must be named, i.e. `Result<(Shared, Local, init::Monotonics), E>`",
+            ));
+        }
+        _ => return Ok(()),
+    };
+
+    extra.init_result = Some(mem::replace(&mut **ty, resources));
+    extra.init_error = Some(error);
+    Ok(())
+}
+
+/// Collects atomic resources from the `#[shared]` struct
+fn parse_shared_struct(fields: &mut Fields, extra: &mut Extra) -> Result<()> {
+    for field in fields.iter_mut() {
+        let ident = match &field.ident {
+            Some(ident) => ident.clone(),
+            // `rtic-syntax` reports unnamed fields
+            None => continue,
+        };
+
+        let marked = match field.attrs.iter().position(|attr| attr_eq(attr, "atomic")) {
+            Some(pos) => {
+                let attr = field.attrs.remove(pos);
+                if !attr.tokens.is_empty() {
+                    return Err(Error::new_spanned(
+                        attr.tokens,
+                        "`#[atomic]` does not take any arguments",
+                    ));
+                }
+                true
+            }
+            None => false,
+        };
+
+        // `#[lock_free]` resources keep their semantics, even if the type is atomic
+        if field.attrs.iter().any(|attr| attr_eq(attr, "lock_free")) {
+            if marked {
+                return Err(Error::new(
+                    ident.span(),
+                    "resource can not be both `#[atomic]` and `#[lock_free]`",
+                ));
+            }
+            continue;
+        }
+
+        if marked || is_atomic_type(&field.ty) {
+            extra.atomic_resources.insert(ident);
+        }
+    }
+
+    Ok(())
+}
+
+/// Atomic types of `std::sync::atomic`, other types have to be marked `#[atomic]`
+const ATOMIC_TYPES: &[&str] = &[
+    "AtomicBool",
+    "AtomicI8",
+    "AtomicI16",
+    "AtomicI32",
+    "AtomicI64",
+    "AtomicIsize",
+    "AtomicU8",
+    "AtomicU16",
+    "AtomicU32",
+    "AtomicU64",
+    "AtomicUsize",
+    "AtomicPtr",
+];
+
+/// Checks if type is one of the std atomic types, i.e. `AtomicU32` or `std::sync::atomic::AtomicBool`
+fn is_atomic_type(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => path
+            .path
+            .segments
+            .last()
+            .map(|segment| ATOMIC_TYPES.iter().any(|name| segment.ident == name))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Checks if attribute is `#[name]`
+fn attr_eq(attr: &Attribute, name: &str) -> bool {
+    attr.style == AttrStyle::Outer && attr.path.is_ident(name)
+}
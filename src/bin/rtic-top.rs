@@ -0,0 +1,236 @@
+//! Top-like view of a running application built with the `shm` feature
+//!
+//! Usage: `rtic-top [pid] [interval ms]`. Without a pid, the only application that publishes
+//! statistics is shown.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs,
+    time::{Duration, Instant},
+};
+
+use rtic::shm::{self, Region};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let pid = match args.next() {
+        Some(pid) => pid.parse().unwrap_or_else(|_| exit("invalid pid")),
+        None => find_pid(),
+    };
+    let interval = args
+        .next()
+        .map(|ms| ms.parse().unwrap_or_else(|_| exit("invalid interval")))
+        .map_or(Duration::from_secs(1), Duration::from_millis);
+
+    let mut prev: Option<Sample> = None;
+    loop {
+        if !alive(pid) {
+            exit(&format!("process {} exited", pid));
+        }
+        let region = match shm::read(pid) {
+            Ok(region) => region,
+            Err(err) => exit(&format!(
+                "failed to read {}: {}",
+                shm::path(pid).display(),
+                err
+            )),
+        };
+        let sample = Sample::new(region);
+
+        // Clear screen and move the cursor home
+        print!("\x1b[2J\x1b[H{}", render(&sample, prev.as_ref()));
+        prev = Some(sample);
+        std::thread::sleep(interval);
+    }
+}
+
+/// Region with CPU times of the dispatcher threads at the time it was read
+struct Sample {
+    region: Region,
+    read_at: Instant,
+    cpu: HashMap<u32, Duration>,
+}
+
+impl Sample {
+    fn new(region: Region) -> Self {
+        let cpu = region
+            .levels
+            .iter()
+            .filter_map(|level| Some((level.tid, thread_cpu(region.pid, level.tid)?)))
+            .collect();
+
+        Self {
+            region,
+            read_at: Instant::now(),
+            cpu,
+        }
+    }
+}
+
+fn render(sample: &Sample, prev: Option<&Sample>) -> String {
+    let region = &sample.region;
+    let elapsed = prev.map(|prev| sample.read_at - prev.read_at);
+    let mut out = String::new();
+
+    let _ = writeln!(out, "rtic-top - pid {}\n", region.pid);
+    let _ = writeln!(
+        out,
+        "{:>4} {:<16} {:>8} {:>6} {:>7} {:>7} {:>10}  RUNNING",
+        "PRIO", "THREAD", "TID", "CPU%", "QUEUED", "SCHED", "DISPATCHED"
+    );
+    for level in &region.levels {
+        let cpu = elapsed
+            .zip(prev.and_then(|prev| prev.cpu.get(&level.tid)))
+            .zip(sample.cpu.get(&level.tid))
+            .map(|((elapsed, prev), now)| {
+                format!("{:.1}", percent(now.saturating_sub(*prev), elapsed))
+            })
+            .unwrap_or_else(|| "-".into());
+        let running = match (&level.running, level.alive) {
+            (_, false) => "(exited)".into(),
+            (Some((task, time)), true) => format!("{} for {:?}", task, time),
+            (None, true) => String::new(),
+        };
+        let _ = writeln!(
+            out,
+            "{:>4} {:<16} {:>8} {:>6} {:>7} {:>7} {:>10}  {}",
+            level.priority,
+            level.thread,
+            level.tid,
+            cpu,
+            level.queued,
+            level.scheduled,
+            level.dispatched,
+            running
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "\n{:>4} {:<20} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "PRIO", "TASK", "RATE/s", "QUEUE", "HIGH", "FAILED", "MISSED", "EXEC p50", "EXEC p99"
+    );
+    for task in &region.tasks {
+        let rate = elapsed
+            .zip(prev.and_then(|prev| prev.region.tasks.iter().find(|t| t.name == task.name)))
+            .map(|(elapsed, prev)| {
+                let runs = task.count.saturating_sub(prev.count);
+                format!("{:.1}", runs as f64 / elapsed.as_secs_f64())
+            })
+            .unwrap_or_else(|| "-".into());
+        let _ = writeln!(
+            out,
+            "{:>4} {:<20} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            task.priority,
+            task.name,
+            if region.stats { rate } else { "-".into() },
+            format!("{}/{}", task.used, task.capacity),
+            task.high_water,
+            task.spawn_failures,
+            task.deadline_misses,
+            duration(region.stats, task.wall_time.p50),
+            duration(region.stats, task.wall_time.p99),
+        );
+    }
+
+    if region.stats {
+        let _ = writeln!(
+            out,
+            "\n{:<20} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "TASK", "LAT p99", "LAT max", "EXEC max", "CPU p99", "CPU max"
+        );
+        for task in &region.tasks {
+            let _ = writeln!(
+                out,
+                "{:<20} {:>9} {:>9} {:>9} {:>9} {:>9}",
+                task.name,
+                duration(true, task.latency.p99),
+                duration(true, task.latency.max),
+                duration(true, task.wall_time.max),
+                duration(true, task.cpu_time.p99),
+                duration(true, task.cpu_time.max),
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "\n{:<20} {:>9} {:>9} {:>9} {:>9}",
+            "RESOURCE", "LOCKS", "WAIT p99", "WAIT max", "HELD max"
+        );
+        for lock in &region.locks {
+            let _ = writeln!(
+                out,
+                "{:<20} {:>9} {:>9} {:>9} {:>9}",
+                lock.name,
+                lock.count,
+                duration(true, lock.wait.p99),
+                duration(true, lock.wait.max),
+                duration(true, lock.held.max),
+            );
+        }
+    } else {
+        let _ = writeln!(
+            out,
+            "\nrates, timing and locks require the `stats` feature of the application"
+        );
+    }
+
+    out
+}
+
+fn percent(part: Duration, whole: Duration) -> f64 {
+    part.as_secs_f64() / whole.as_secs_f64() * 100.0
+}
+
+fn duration(available: bool, duration: Duration) -> String {
+    if available {
+        format!("{:.1?}", duration)
+    } else {
+        "-".into()
+    }
+}
+
+/// Reads user and system CPU time of a thread from `/proc`
+fn thread_cpu(pid: u32, tid: u32) -> Option<Duration> {
+    let stat = fs::read_to_string(format!("/proc/{}/task/{}/stat", pid, tid)).ok()?;
+    // The thread name in parentheses may contain spaces
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+    // `utime` and `stime` are fields 14 and 15, the state (field 3) comes first after the name
+    let utime: u64 = fields.nth(11)?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    Some(Duration::from_nanos(
+        (utime + stime) * 1_000_000_000 / ticks.max(1),
+    ))
+}
+
+fn alive(pid: u32) -> bool {
+    fs::metadata(format!("/proc/{}", pid)).is_ok()
+}
+
+/// Finds the only process that publishes statistics
+fn find_pid() -> u32 {
+    let pids: Vec<u32> = fs::read_dir("/dev/shm")
+        .unwrap_or_else(|err| exit(&format!("failed to read /dev/shm: {}", err)))
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?.strip_prefix("rtic-")?.parse().ok()
+        })
+        .filter(|&pid| alive(pid))
+        .collect();
+
+    match pids[..] {
+        [pid] => pid,
+        [] => exit("no running application found, is it built with the `shm` feature?"),
+        _ => exit(&format!(
+            "several applications are running, pass a pid: {:?}",
+            pids
+        )),
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("rtic-top: {}", message);
+    std::process::exit(1);
+}
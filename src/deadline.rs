@@ -0,0 +1,129 @@
+//! Deadline-miss detection of software tasks
+//!
+//! A relative deadline is set with `#[task(deadline = "10ms")]` and counted from the release of
+//! each instance, i.e. the `spawn` call or the instant passed to `spawn_at`. When an instance
+//! completes after its deadline, the miss is counted and reported to the function marked with
+//! `#[overrun]` in the app module.
+//!
+//! With `#[task(deadline = "10ms", overrun = "skip")]` queued instances whose deadline has already
+//! passed when the dispatcher gets to them are dropped without running, so that a task catches up
+//! after an overrun instead of running every stale instance.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// A task instance that missed its deadline
+#[derive(Debug, Clone)]
+pub struct Overrun {
+    /// Name of the task
+    pub task: &'static str,
+    /// Priority of the task
+    pub priority: u8,
+    /// Relative deadline of the task
+    pub deadline: Duration,
+    /// Time past the deadline when the instance completed or was skipped
+    pub late: Duration,
+    /// Whether the instance was skipped instead of run
+    pub skipped: bool,
+}
+
+impl fmt::Display for Overrun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = if self.skipped { "skipped" } else { "completed" };
+        write!(
+            f,
+            "task {} {} {:?} after its deadline of {:?}",
+            self.task, action, self.late, self.deadline
+        )
+    }
+}
+
+/// Deadline and miss counters of a single task
+#[derive(Debug)]
+pub struct TaskDeadline {
+    /// Name of the task
+    pub name: &'static str,
+    /// Priority of the task
+    pub priority: u8,
+    /// Relative deadline, counted from release
+    pub deadline: Duration,
+    /// Whether late instances are skipped
+    pub skip: bool,
+    missed: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl TaskDeadline {
+    pub const fn new(name: &'static str, priority: u8, deadline: Duration, skip: bool) -> Self {
+        Self {
+            name,
+            priority,
+            deadline,
+            skip,
+            missed: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of instances that completed after their deadline
+    pub fn missed(&self) -> u64 {
+        self.missed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of instances that were skipped
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Called by the dispatcher before a task runs, returns `false` if the instance is skipped
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn start(&self, released_at: Option<Instant>, handler: fn(&Overrun)) -> bool {
+        if !self.skip {
+            return true;
+        }
+
+        match self.late(released_at) {
+            Some(late) => {
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+                handler(&self.overrun(late, true));
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Called by the dispatcher after a task returns
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn finish(&self, released_at: Option<Instant>, handler: fn(&Overrun)) {
+        if let Some(late) = self.late(released_at) {
+            self.missed.fetch_add(1, Ordering::Relaxed);
+            handler(&self.overrun(late, false));
+        }
+    }
+
+    /// Returns the time past the deadline, if it has passed
+    fn late(&self, released_at: Option<Instant>) -> Option<Duration> {
+        let deadline = released_at? + self.deadline;
+        Instant::now()
+            .checked_duration_since(deadline)
+            .filter(|late| !late.is_zero())
+    }
+
+    fn overrun(&self, late: Duration, skipped: bool) -> Overrun {
+        Overrun {
+            task: self.name,
+            priority: self.priority,
+            deadline: self.deadline,
+            late,
+            skipped,
+        }
+    }
+}
+
+/// Default handler, which only counts the miss
+pub fn ignore(_: &Overrun) {}
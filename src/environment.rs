@@ -0,0 +1,260 @@
+//! Startup audit of the real-time environment
+//!
+//! Checks the system against the prerequisites listed in README, so that a misconfigured system
+//! is reported at startup instead of showing up as latency spikes or an unhelpful panic.
+
+use std::{fmt, fs, io, mem::MaybeUninit};
+
+use crate::sched::{self, Mode};
+
+/// Action taken when the audit finds issues
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Skip the audit
+    Ignore,
+    /// Print issues to stderr and continue
+    Warn,
+    /// Print issues to stderr and return an [`AuditError`] if any of them is an [`Severity::Error`]
+    Fail,
+    /// Print issues to stderr and continue without real-time scheduling if it is not available
+    Degrade,
+}
+
+/// Severity of an [`Issue`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Real-time scheduling works, but latency guarantees are weaker
+    Warning,
+    /// Real-time scheduling is not possible
+    Error,
+}
+
+/// A problem found in the environment
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// Issues of [`Severity::Error`] found by an audit with [`Policy::Fail`]
+#[derive(Debug, Clone)]
+pub struct AuditError {
+    pub errors: Vec<Issue>,
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "real-time environment audit failed with {} error(s)",
+            self.errors.len()
+        )?;
+        for issue in &self.errors {
+            write!(f, "; {}", issue.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+/// Audits the environment and handles the found issues according to `policy`
+///
+/// `max_priority` is the highest `SCHED_FIFO` priority used by the application.
+pub fn audit(policy: Policy, max_priority: u8) -> Result<(), AuditError> {
+    if policy == Policy::Ignore || sched::mode() != Mode::RealTime {
+        return Ok(());
+    }
+
+    let issues = issues(max_priority);
+    for issue in &issues {
+        eprintln!("rtic: {}", issue);
+    }
+
+    // Warnings only weaken latency guarantees, so they never stop the application
+    let errors: Vec<_> = issues
+        .into_iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .collect();
+
+    match policy {
+        Policy::Fail if !errors.is_empty() => return Err(AuditError { errors }),
+        Policy::Degrade if !errors.is_empty() => {
+            eprintln!("rtic: continuing without real-time scheduling");
+            sched::set_mode(Mode::Normal);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Inspects the environment and returns all found issues
+pub fn issues(max_priority: u8) -> Vec<Issue> {
+    let mut issues = vec![];
+
+    if !is_preempt_rt() {
+        issues.push(warning("kernel is not PREEMPT_RT, latency is not bounded"));
+    }
+
+    if let Some(runtime) = read_trimmed("/proc/sys/kernel/sched_rt_runtime_us") {
+        if runtime != "-1" {
+            let period = read_trimmed("/proc/sys/kernel/sched_rt_period_us")
+                .unwrap_or_else(|| "?".to_string());
+            issues.push(warning(format!(
+                "real-time throttling is enabled ({}us every {}us), set `kernel.sched_rt_runtime_us = -1`",
+                runtime, period
+            )));
+        }
+    }
+
+    let affinity = affinity().unwrap_or_default();
+
+    let slow_cpus: Vec<_> = affinity
+        .iter()
+        .filter(|&&cpu| {
+            read_trimmed(&format!(
+                "/sys/devices/system/cpu/cpu{}/cpufreq/scaling_governor",
+                cpu
+            ))
+            .map(|governor| governor != "performance")
+            .unwrap_or(false)
+        })
+        .copied()
+        .collect();
+    if !slow_cpus.is_empty() {
+        issues.push(warning(format!(
+            "CPU frequency scaling is enabled on CPU(s) {}, use `cpufreq-set -g performance`",
+            format_cpus(&slow_cpus)
+        )));
+    }
+
+    let isolated = read_trimmed("/sys/devices/system/cpu/isolated")
+        .map(|list| parse_cpus(&list))
+        .unwrap_or_default();
+    if isolated.is_empty() {
+        issues.push(warning(
+            "no CPUs are isolated, use `isolcpus` kernel parameter",
+        ));
+    } else if affinity.iter().any(|cpu| !isolated.contains(cpu)) {
+        issues.push(warning(format!(
+            "process is not pinned to isolated CPU(s) {}, use `taskset`",
+            format_cpus(&isolated)
+        )));
+    }
+
+    if !can_set_priority(max_priority) {
+        issues.push(Issue {
+            severity: Severity::Error,
+            message: format!(
+                "missing CAP_SYS_NICE capability or RLIMIT_RTPRIO >= {} to use SCHED_FIFO, run as root",
+                max_priority
+            ),
+        });
+    }
+
+    issues
+}
+
+fn warning(message: impl Into<String>) -> Issue {
+    Issue {
+        severity: Severity::Warning,
+        message: message.into(),
+    }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Checks `/sys/kernel/realtime` and falls back to the kernel version string
+fn is_preempt_rt() -> bool {
+    if let Some(realtime) = read_trimmed("/sys/kernel/realtime") {
+        return realtime == "1";
+    }
+
+    let mut uts = MaybeUninit::<libc::utsname>::uninit();
+    if unsafe { libc::uname(uts.as_mut_ptr()) } != 0 {
+        return false;
+    }
+
+    let version = unsafe { std::ffi::CStr::from_ptr(uts.assume_init_ref().version.as_ptr()) };
+    let version = version.to_string_lossy();
+    version.contains("PREEMPT_RT") || version.contains("PREEMPT RT")
+}
+
+/// Returns CPUs the current process is allowed to run on
+fn affinity() -> io::Result<Vec<usize>> {
+    let mut set = MaybeUninit::<libc::cpu_set_t>::zeroed();
+
+    unsafe {
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set.as_mut_ptr()) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let set = set.assume_init();
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect())
+    }
+}
+
+/// Checks whether the process is allowed to use `SCHED_FIFO` with a given priority
+pub(crate) fn can_set_priority(priority: u8) -> bool {
+    if unsafe { libc::geteuid() } == 0 {
+        return true;
+    }
+
+    // Effective capabilities are a hex bitmask
+    const CAP_SYS_NICE: u32 = 23;
+    let has_cap = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))
+                .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        })
+        .map(|caps| caps & (1 << CAP_SYS_NICE) != 0)
+        .unwrap_or(false);
+    if has_cap {
+        return true;
+    }
+
+    let mut limit = MaybeUninit::<libc::rlimit>::uninit();
+    unsafe {
+        libc::getrlimit(libc::RLIMIT_RTPRIO, limit.as_mut_ptr()) == 0
+            && limit.assume_init().rlim_cur >= priority as libc::rlim_t
+    }
+}
+
+/// Parses kernel CPU list format, i.e. `0-2,5`
+fn parse_cpus(list: &str) -> Vec<usize> {
+    list.split(',')
+        .filter_map(|range| match range.split_once('-') {
+            Some((start, end)) => Some(start.parse().ok()?..=end.parse().ok()?),
+            None => {
+                let cpu = range.parse().ok()?;
+                Some(cpu..=cpu)
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+fn format_cpus(cpus: &[usize]) -> String {
+    cpus.iter()
+        .map(|cpu| cpu.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
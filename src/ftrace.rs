@@ -0,0 +1,128 @@
+//! Task and lock events in the kernel trace
+//!
+//! With the `ftrace` feature, task executions, spawns and locks are written to the ftrace
+//! `trace_marker` file as `B|<pid>|<name>` and `E|<pid>` markers, the format used by Android
+//! `atrace`. They appear on the same timeline as kernel scheduler events, i.e. when recording with
+//! `trace-cmd record -e sched` or Perfetto, which shows them as slices of each thread.
+//!
+//! Writing a marker is a single `write` syscall into the kernel ring buffer and does not block.
+//! Tracing must be accessible to the process, which usually requires root. Without the feature or
+//! access, nothing is written.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::io::AsRawFd,
+    sync::OnceLock,
+};
+
+/// Whether markers are written
+pub const ENABLED: bool = cfg!(feature = "ftrace");
+
+/// Locations of `trace_marker`, depending on where tracefs is mounted
+const PATHS: [&str; 2] = [
+    "/sys/kernel/tracing/trace_marker",
+    "/sys/kernel/debug/tracing/trace_marker",
+];
+
+/// Longest marker, longer names are truncated
+const MAX_LEN: usize = 128;
+
+static MARKER: OnceLock<Option<(File, u32)>> = OnceLock::new();
+
+/// Opens `trace_marker`
+///
+/// Called by the generated code before `#[init]`, markers written before are lost.
+#[doc(hidden)]
+pub fn open() {
+    if !ENABLED {
+        return;
+    }
+
+    MARKER.get_or_init(|| {
+        let file = PATHS
+            .iter()
+            .find_map(|path| OpenOptions::new().write(true).open(path).ok());
+        if file.is_none() {
+            eprintln!(
+                "rtic: ftrace: failed to open trace_marker, is tracefs mounted and accessible?"
+            );
+        }
+        file.map(|file| (file, std::process::id()))
+    });
+}
+
+/// Writes a free-form marker, i.e. `write("frame received")`
+pub fn write(message: &str) {
+    if let Some((file, _)) = marker() {
+        raw_write(file, message.as_bytes());
+    }
+}
+
+/// Starts a slice on the current thread
+#[doc(hidden)]
+#[inline(always)]
+pub fn begin(name: &str) {
+    if let Some((file, pid)) = marker() {
+        let mut buf = Buffer::new();
+        let _ = write!(buf, "B|{}|{}", pid, name);
+        raw_write(file, buf.as_bytes());
+    }
+}
+
+/// Ends the slice started by [`begin`]
+#[doc(hidden)]
+#[inline(always)]
+pub fn end() {
+    if let Some((file, pid)) = marker() {
+        let mut buf = Buffer::new();
+        let _ = write!(buf, "E|{}", pid);
+        raw_write(file, buf.as_bytes());
+    }
+}
+
+#[inline(always)]
+fn marker() -> Option<&'static (File, u32)> {
+    if ENABLED {
+        MARKER.get()?.as_ref()
+    } else {
+        None
+    }
+}
+
+// `File::write` would need `&mut`, the kernel handles concurrent writes
+fn raw_write(file: &File, bytes: &[u8]) {
+    unsafe { libc::write(file.as_raw_fd(), bytes.as_ptr() as *const _, bytes.len()) };
+}
+
+/// Stack buffer, so that markers do not allocate
+struct Buffer {
+    bytes: [u8; MAX_LEN],
+    len: usize,
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Self {
+            bytes: [0; MAX_LEN],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(MAX_LEN - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&buf[..len]);
+        self.len += len;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
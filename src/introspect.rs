@@ -0,0 +1,107 @@
+//! Snapshot of run queues and task queues of a running application
+//!
+//! `app::introspect()` (or `AppHandle::introspect`) returns the state of every priority level and
+//! every software task. Values are read from counters that the dispatchers and spawns update anyway,
+//! so taking a snapshot does not disturb the real-time threads. Counters are read one by one and
+//! may be slightly inconsistent with each other.
+
+use std::time::Duration;
+
+use crate::{
+    runtime::{monotonic_nanos, Progress},
+    slab::SlabSender,
+};
+
+/// State of a running application
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Priority levels, ordered by priority
+    pub levels: Vec<Level>,
+    /// Software tasks
+    pub tasks: Vec<Task>,
+}
+
+/// State of a priority level and its dispatcher thread
+#[derive(Debug, Clone)]
+pub struct Level {
+    /// Priority of the level
+    pub priority: u8,
+    /// OS thread name of the dispatcher
+    pub thread: &'static str,
+    /// Linux thread id of the dispatcher
+    pub tid: u32,
+    /// Number of tasks spawned for immediate execution and waiting in the run queue
+    pub queued: u64,
+    /// Number of tasks spawned for a later instant and not yet dispatched
+    pub scheduled: u64,
+    /// Name of the running task and how long it has been running
+    pub running: Option<(&'static str, Duration)>,
+    /// Number of tasks run so far
+    pub dispatched: u64,
+    /// Whether the dispatcher thread is running, it exits on shutdown or a panic
+    pub alive: bool,
+}
+
+/// State of a software task
+#[derive(Debug, Clone)]
+pub struct Task {
+    /// Name of the task
+    pub name: &'static str,
+    /// Priority of the task
+    pub priority: u8,
+    /// Number of spawned instances that have not started yet
+    pub used: usize,
+    /// Maximum number of spawned instances, the `capacity` argument of `#[task]`
+    pub capacity: usize,
+    /// Highest value of `used` so far
+    pub high_water: usize,
+    /// Number of spawns that failed because the task was at capacity
+    pub spawn_failures: usize,
+}
+
+impl Level {
+    /// Reads the state of a dispatcher, `tasks` are names of all tasks in the app
+    #[doc(hidden)]
+    pub fn new(
+        priority: u8,
+        thread: &'static str,
+        progress: &Progress,
+        tasks: &[&'static str],
+    ) -> Self {
+        let snapshot = progress.snapshot();
+        let running = snapshot.running().map(|task| {
+            let time = snapshot.running_for(monotonic_nanos()).unwrap_or_default();
+            (tasks[task as usize], time)
+        });
+
+        Self {
+            priority,
+            thread,
+            tid: progress.tid(),
+            queued: snapshot.pending(),
+            scheduled: snapshot.scheduled(),
+            running,
+            dispatched: snapshot.started(),
+            alive: snapshot.is_alive(),
+        }
+    }
+}
+
+impl Task {
+    /// Reads the state of a task input queue
+    #[doc(hidden)]
+    pub fn new<T, const N: usize>(
+        name: &'static str,
+        priority: u8,
+        queue: &SlabSender<T, N>,
+    ) -> Self {
+        Self {
+            name,
+            priority,
+            used: queue.used(),
+            capacity: queue.capacity(),
+            high_water: queue.high_water(),
+            spawn_failures: queue.failures(),
+        }
+    }
+}
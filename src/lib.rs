@@ -14,6 +14,7 @@ pub use lazy_static;
 pub use linux_rtic_macros::app;
 pub use pcp_mutex::PcpMutex;
 pub use rtic_core::{prelude as mutex_prelude, Exclusive, Mutex};
+pub use runtime::{AppHandle, Config, Error};
 
 use std::cell::UnsafeCell;
 
@@ -24,13 +25,41 @@ pub use tracing_chrome;
 #[cfg(feature = "profiling")]
 pub use tracing_subscriber;
 
+pub mod deadline;
+pub mod environment;
+pub mod ftrace;
+pub mod introspect;
+pub mod log;
+pub mod memory;
+pub mod metrics;
+pub mod pool;
+pub mod profiling;
+pub mod runtime;
+pub mod sched;
+pub mod shm;
 pub mod slab;
+pub mod stats;
+pub mod systemd;
+pub mod trace;
+pub mod watchdog;
 
+mod ring;
+
+/// Sets scheduling policy and priority of the current thread according to [`sched::mode`]
 pub fn init_thread_state(priority: pcp_mutex::Priority) {
-    #[cfg(feature = "rt")]
-    pcp_mutex::thread::init_fifo_priority(priority).expect("Error setting thread priority");
+    sched::init_thread(priority);
+    trace::register_thread(priority);
+    log::register_thread(priority);
 }
 
+/// Compile time assertion that `T` can be moved between threads
+#[doc(hidden)]
+pub const fn assert_send<T: Send + ?Sized>() {}
+
+/// Compile time assertion that `T` can be shared between threads
+#[doc(hidden)]
+pub const fn assert_sync<T: Sync + ?Sized>() {}
+
 /// Internal replacement for `static mut T`
 #[repr(transparent)]
 pub struct RacyCell<T>(UnsafeCell<T>);
@@ -43,12 +72,21 @@ impl<T> RacyCell<T> {
     }
 
     /// Get `&mut T`
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that there are no other references to the value.
+    #[allow(clippy::mut_from_ref)]
     #[inline(always)]
     pub unsafe fn get_mut_unchecked(&self) -> &mut T {
         &mut *self.0.get()
     }
 
     /// Get `&T`
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that there are no mutable references to the value.
     #[inline(always)]
     pub unsafe fn get_unchecked(&self) -> &T {
         &*self.0.get()
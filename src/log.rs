@@ -0,0 +1,698 @@
+//! Deferred logging that is safe to use from real-time tasks
+//!
+//! `println!` takes the stdout lock and may block on I/O, which stalls a `SCHED_FIFO` thread and
+//! every task below it. [`crate::info!`] and the other macros only copy the arguments next to a
+//! pointer to the format string into a lock-free buffer of the calling thread. A low priority
+//! thread formats the records and writes them to stderr:
+//!
+//! ```ignore
+//! rtic::info!("control output {:.3} after {} runs", output, runs);
+//! ```
+//!
+//! Arguments are integers, floats, `bool`, `char`, `&'static str` or `Duration`, at most
+//! [`MAX_ARGS`] of them and passed by position. Records are dropped and counted if a buffer fills
+//! up faster than it is written, the count is printed with the next written records.
+//!
+//! Recording is lock-free on threads registered by [`crate::init_thread_state`], which covers the
+//! dispatchers, init and idle. Any other thread allocates its buffer and takes a global lock with
+//! its first record, so it should not be a real-time thread or it should log once before its
+//! time-critical part. Buffers are freed once their thread has exited and its records are written.
+//!
+//! The level is set with [`set_max_level`] or the `RTIC_LOG` environment variable (`off`, `error`,
+//! `warn`, `info`, `debug` or `trace`), by default `info`. With the `log` feature, records of the
+//! `log` crate go through the same buffers. They are formatted by the calling thread into a fixed
+//! size buffer, which does not block but takes longer than copying the arguments, see [`Logger`].
+
+use std::{
+    cell::OnceCell,
+    fmt,
+    io::{self, Write},
+    ops::Deref,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{
+    ring::{self, Ring, Rings},
+    runtime::monotonic_nanos,
+};
+
+/// Maximum number of arguments of a record
+pub const MAX_ARGS: usize = 8;
+
+/// Number of records each thread can buffer between writes
+const CAPACITY: usize = 256;
+
+/// Length of messages formatted by the calling thread, longer messages are truncated
+const TEXT_LEN: usize = 160;
+
+/// Interval at which buffers are written
+const PERIOD: Duration = Duration::from_millis(10);
+
+/// Severity of a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        })
+    }
+}
+
+/// Highest enabled level, zero disables logging
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Sets the most verbose level that is recorded, `None` disables logging
+pub fn set_max_level(level: Option<Level>) {
+    MAX_LEVEL.store(level.map_or(0, |level| level as u8), Ordering::Relaxed);
+    #[cfg(feature = "log")]
+    ::log::set_max_level(match level {
+        None => ::log::LevelFilter::Off,
+        Some(Level::Error) => ::log::LevelFilter::Error,
+        Some(Level::Warn) => ::log::LevelFilter::Warn,
+        Some(Level::Info) => ::log::LevelFilter::Info,
+        Some(Level::Debug) => ::log::LevelFilter::Debug,
+        Some(Level::Trace) => ::log::LevelFilter::Trace,
+    });
+}
+
+/// Returns the most verbose level that is recorded
+pub fn max_level() -> Option<Level> {
+    match MAX_LEVEL.load(Ordering::Relaxed) {
+        0 => None,
+        1 => Some(Level::Error),
+        2 => Some(Level::Warn),
+        3 => Some(Level::Info),
+        4 => Some(Level::Debug),
+        _ => Some(Level::Trace),
+    }
+}
+
+/// Returns whether records of `level` are recorded
+#[inline(always)]
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Parses `off`, `error`, `warn`, `info`, `debug` or `trace`
+fn parse_level(s: &str) -> Result<Option<Level>, ()> {
+    match &*s.trim().to_ascii_lowercase() {
+        "off" => Ok(None),
+        "error" => Ok(Some(Level::Error)),
+        "warn" => Ok(Some(Level::Warn)),
+        "info" => Ok(Some(Level::Info)),
+        "debug" => Ok(Some(Level::Debug)),
+        "trace" => Ok(Some(Level::Trace)),
+        _ => Err(()),
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_level(s)?.ok_or(())
+    }
+}
+
+/// Static part of a record, created by the macros at every call site
+#[doc(hidden)]
+pub struct Metadata {
+    pub level: Level,
+    pub target: &'static str,
+    /// Formats the arguments with the format string of the call site
+    pub format: fn(&[Value], &mut fmt::Formatter<'_>) -> fmt::Result,
+}
+
+/// Argument of a record, copied into the buffer and formatted by the writer thread
+#[derive(Clone, Copy)]
+pub enum Value {
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+    Char(char),
+    Str(&'static str),
+    Duration(Duration),
+}
+
+macro_rules! impl_from {
+    ($($ty:ty => $variant:ident as $as:ty),* $(,)?) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(value: $ty) -> Self {
+                    Value::$variant(value as $as)
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(
+    i8 => I64 as i64, i16 => I64 as i64, i32 => I64 as i64, i64 => I64 as i64, isize => I64 as i64,
+    u8 => U64 as u64, u16 => U64 as u64, u32 => U64 as u64, u64 => U64 as u64, usize => U64 as u64,
+    f32 => F64 as f64, f64 => F64 as f64,
+    bool => Bool as bool, char => Char as char,
+);
+
+impl From<&'static str> for Value {
+    fn from(value: &'static str) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<Duration> for Value {
+    fn from(value: Duration) -> Self {
+        Value::Duration(value)
+    }
+}
+
+/// Forwards a formatting trait to the argument, falling back to `Display` for types without it
+macro_rules! impl_fmt {
+    ($trait:ident: $($variant:ident),*) => {
+        impl fmt::$trait for Value {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    $(Value::$variant(value) => fmt::$trait::fmt(value, f),)*
+                    #[allow(unreachable_patterns)]
+                    _ => fmt::Display::fmt(self, f),
+                }
+            }
+        }
+    };
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::I64(value) => fmt::Display::fmt(value, f),
+            Value::U64(value) => fmt::Display::fmt(value, f),
+            Value::F64(value) => fmt::Display::fmt(value, f),
+            Value::Bool(value) => fmt::Display::fmt(value, f),
+            Value::Char(value) => fmt::Display::fmt(value, f),
+            Value::Str(value) => fmt::Display::fmt(value, f),
+            // `Duration` has no `Display`
+            Value::Duration(value) => fmt::Debug::fmt(value, f),
+        }
+    }
+}
+
+impl_fmt!(Debug: I64, U64, F64, Bool, Char, Str, Duration);
+impl_fmt!(LowerHex: I64, U64);
+impl_fmt!(UpperHex: I64, U64);
+impl_fmt!(Binary: I64, U64);
+impl_fmt!(Octal: I64, U64);
+impl_fmt!(LowerExp: I64, U64, F64);
+impl_fmt!(UpperExp: I64, U64, F64);
+
+/// Formats the arguments of a record with the format string of its call site
+struct Deferred<'a> {
+    metadata: &'static Metadata,
+    args: &'a [Value],
+}
+
+impl fmt::Display for Deferred<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.metadata.format)(self.args, f)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Message {
+    /// Recorded by the macros of this module
+    Deferred {
+        metadata: &'static Metadata,
+        args: [Value; MAX_ARGS],
+        len: u8,
+    },
+    /// Formatted by the calling thread, i.e. records of the `log` crate
+    #[cfg_attr(not(feature = "log"), allow(dead_code))]
+    Text {
+        level: Level,
+        target: &'static str,
+        text: [u8; TEXT_LEN],
+        len: u8,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    timestamp: u64,
+    message: Message,
+}
+
+static EMPTY: Metadata = Metadata {
+    level: Level::Trace,
+    target: "",
+    format: |_, _| Ok(()),
+};
+
+thread_local! {
+    static BUFFER: OnceCell<ring::Local<Record>> = const { OnceCell::new() };
+}
+
+/// Buffers of all threads that logged
+static BUFFERS: Rings<Record> = Rings::new(
+    &BUFFER,
+    CAPACITY,
+    Record {
+        timestamp: 0,
+        message: Message::Deferred {
+            metadata: &EMPTY,
+            args: [Value::U64(0); MAX_ARGS],
+            len: 0,
+        },
+    },
+);
+static STOP: AtomicBool = AtomicBool::new(false);
+static WRITER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+/// Allocates the buffer of the current thread, so that logging does not allocate or lock
+///
+/// Called by [`crate::init_thread_state`], other threads allocate it with their first record.
+#[doc(hidden)]
+pub fn register_thread(priority: u8) {
+    BUFFERS.with(priority, |_| {});
+}
+
+/// Records the arguments of a call site, used by the macros
+#[doc(hidden)]
+#[inline]
+pub fn record(metadata: &'static Metadata, values: &[Value]) {
+    let mut args = [Value::U64(0); MAX_ARGS];
+    args[..values.len()].copy_from_slice(values);
+    BUFFERS.with(0, |buffer| {
+        buffer.push(Record {
+            timestamp: monotonic_nanos(),
+            message: Message::Deferred {
+                metadata,
+                args,
+                len: values.len() as u8,
+            },
+        })
+    });
+}
+
+/// Returns the number of records dropped because a buffer was full
+pub fn dropped() -> u64 {
+    BUFFERS.dropped()
+}
+
+/// Starts the writer thread
+///
+/// Called by the generated code before `#[init]`. Reads the level from `RTIC_LOG` and installs the
+/// `log` crate backend with the `log` feature.
+#[doc(hidden)]
+pub fn start(name: &str) {
+    if let Ok(value) = std::env::var("RTIC_LOG") {
+        match parse_level(&value) {
+            Ok(level) => set_max_level(level),
+            Err(()) => eprintln!("rtic: log: invalid RTIC_LOG level {:?}", value),
+        }
+    }
+
+    #[cfg(feature = "log")]
+    {
+        if ::log::set_logger(&Logger).is_err() {
+            eprintln!("rtic: log: a logger is already installed");
+        }
+        set_max_level(max_level());
+    }
+
+    let mut writer = WRITER.lock().unwrap();
+    if writer.is_some() {
+        return;
+    }
+
+    let result = crate::runtime::spawn_thread(name, None, || {
+        // Below all tasks, writing must not delay them
+        crate::sched::init_thread(0);
+
+        let mut records = Vec::new();
+        let mut dropped = 0;
+        loop {
+            let stop = STOP.load(Ordering::Relaxed);
+            if let Err(err) = write(&mut records, &mut dropped) {
+                eprintln!("rtic: log: failed to write: {}", err);
+            }
+            if stop {
+                break;
+            }
+            std::thread::sleep(PERIOD);
+        }
+    });
+    match result {
+        Ok(handle) => *writer = Some(handle),
+        // Records stay in the buffers, which are dropped once full
+        Err(err) => eprintln!("rtic: log: {}", err),
+    }
+}
+
+/// Writes remaining records and stops the writer thread
+///
+/// Called when the application stops.
+#[doc(hidden)]
+pub fn stop() {
+    if let Some(handle) = WRITER.lock().unwrap().take() {
+        STOP.store(true, Ordering::Relaxed);
+        handle.join().ok();
+    }
+}
+
+/// Drains all buffers and writes their records to stderr
+fn write(records: &mut Vec<(u64, usize, Record)>, dropped: &mut u64) -> io::Result<()> {
+    let buffers = BUFFERS.buffers();
+    let stderr = io::stderr();
+    let mut out = io::BufWriter::new(stderr.lock());
+    write_buffers(&mut out, &buffers, records, dropped, BUFFERS.dropped())?;
+    out.flush()
+}
+
+/// Drains `buffers` and writes their records in order of time
+///
+/// `dropped` is the number of dropped records reported so far and `total` the number of all
+/// dropped records.
+fn write_buffers(
+    out: &mut impl Write,
+    buffers: &[impl Deref<Target = Ring<Record>>],
+    records: &mut Vec<(u64, usize, Record)>,
+    dropped: &mut u64,
+    total: u64,
+) -> io::Result<()> {
+    for (index, buffer) in buffers.iter().enumerate() {
+        buffer.drain(|record| records.push((record.timestamp, index, *record)));
+    }
+    records.sort_by_key(|&(timestamp, index, _)| (timestamp, index));
+
+    for (_, index, record) in records.drain(..) {
+        let thread = &buffers[index].name;
+        let secs = Duration::from_nanos(record.timestamp).as_secs_f64();
+        match &record.message {
+            Message::Deferred {
+                metadata,
+                args,
+                len,
+            } => {
+                let message = Deferred {
+                    metadata,
+                    args: &args[..*len as usize],
+                };
+                writeln!(
+                    out,
+                    "{:>12.6} {:<5} [{}] {}: {}",
+                    secs, metadata.level, thread, metadata.target, message
+                )?;
+            }
+            Message::Text {
+                level,
+                target,
+                text,
+                len,
+            } => {
+                let text = String::from_utf8_lossy(&text[..*len as usize]);
+                writeln!(
+                    out,
+                    "{:>12.6} {:<5} [{}] {}: {}",
+                    secs, level, thread, target, text
+                )?;
+            }
+        }
+    }
+
+    if total > *dropped {
+        writeln!(out, "rtic: log: {} records dropped", total - *dropped)?;
+        *dropped = total;
+    }
+
+    Ok(())
+}
+
+/// Backend of the `log` crate, installed by the generated code
+///
+/// Unlike the macros of this module, records are formatted by the calling thread, into a buffer of
+/// 160 bytes on its stack. Longer messages are truncated. This does not block or allocate, unless
+/// the arguments do, but formatting takes longer than copying the arguments.
+#[cfg(feature = "log")]
+pub struct Logger;
+
+#[cfg(feature = "log")]
+impl ::log::Log for Logger {
+    fn enabled(&self, metadata: &::log::Metadata<'_>) -> bool {
+        enabled(level_from_log(metadata.level()))
+    }
+
+    fn log(&self, record: &::log::Record<'_>) {
+        let level = level_from_log(record.level());
+        if !enabled(level) {
+            return;
+        }
+
+        let mut text = Text {
+            bytes: [0; TEXT_LEN],
+            len: 0,
+        };
+        let _ = fmt::Write::write_fmt(&mut text, *record.args());
+        BUFFERS.with(0, |buffer| {
+            buffer.push(Record {
+                timestamp: monotonic_nanos(),
+                message: Message::Text {
+                    level,
+                    target: record.module_path_static().unwrap_or("log"),
+                    text: text.bytes,
+                    len: text.len as u8,
+                },
+            })
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "log")]
+fn level_from_log(level: ::log::Level) -> Level {
+    match level {
+        ::log::Level::Error => Level::Error,
+        ::log::Level::Warn => Level::Warn,
+        ::log::Level::Info => Level::Info,
+        ::log::Level::Debug => Level::Debug,
+        ::log::Level::Trace => Level::Trace,
+    }
+}
+
+/// Stack buffer that truncates at a character boundary, so that formatting does not allocate
+#[cfg(feature = "log")]
+struct Text {
+    bytes: [u8; TEXT_LEN],
+    len: usize,
+}
+
+#[cfg(feature = "log")]
+impl fmt::Write for Text {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(TEXT_LEN - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Records a message at a level, i.e. `log!(Level::Warn, "late by {:?}", late)`
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        const _: () = ::core::assert!(
+            <[()]>::len(&[$({ ::core::stringify!($arg); }),*]) <= $crate::log::MAX_ARGS,
+            "too many arguments, see `rtic::log::MAX_ARGS`",
+        );
+
+        #[allow(unused_mut, unused_variables)]
+        fn format(
+            args: &[$crate::log::Value],
+            f: &mut ::core::fmt::Formatter<'_>,
+        ) -> ::core::fmt::Result {
+            let mut args = args.iter();
+            ::core::write!(f, $fmt $(, { ::core::stringify!($arg); args.next().unwrap() })*)
+        }
+
+        static METADATA: $crate::log::Metadata = $crate::log::Metadata {
+            level: $level,
+            target: ::core::module_path!(),
+            format,
+        };
+
+        if $crate::log::enabled(METADATA.level) {
+            $crate::log::record(&METADATA, &[$($crate::log::Value::from($arg)),*]);
+        }
+    }};
+}
+
+/// Records a message at [`log::Level::Error`](crate::log::Level::Error)
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+/// Records a message at [`log::Level::Warn`](crate::log::Level::Warn)
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+/// Records a message at [`log::Level::Info`](crate::log::Level::Info)
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+/// Records a message at [`log::Level::Debug`](crate::log::Level::Debug)
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+/// Records a message at [`log::Level::Trace`](crate::log::Level::Trace)
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Trace, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TEST: Metadata = Metadata {
+        level: Level::Info,
+        target: "test",
+        format: |args, f| write!(f, "{}", args[0]),
+    };
+
+    fn record(timestamp: u64, value: u64) -> Record {
+        let mut args = [Value::U64(0); MAX_ARGS];
+        args[0] = Value::U64(value);
+        Record {
+            timestamp,
+            message: Message::Deferred {
+                metadata: &TEST,
+                args,
+                len: 1,
+            },
+        }
+    }
+
+    fn buffer(name: &str) -> Ring<Record> {
+        Ring::new(CAPACITY, record(0, 0), 0, name.to_owned(), 1, 0)
+    }
+
+    fn write_string(buffers: &[&Ring<Record>], dropped: &mut u64) -> String {
+        let total = buffers.iter().map(|buffer| buffer.dropped()).sum();
+        let mut out = Vec::new();
+        write_buffers(&mut out, buffers, &mut Vec::new(), dropped, total).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Returns the messages of the written lines, without time, level, thread and target
+    fn messages(output: &str) -> Vec<&str> {
+        output
+            .lines()
+            .map(|line| line.split_once(": ").map_or(line, |(_, message)| message))
+            .collect()
+    }
+
+    #[test]
+    fn dropped_reported_once() {
+        let buffer = buffer("writer");
+        for i in 0..CAPACITY as u64 + 3 {
+            buffer.push(record(i, i));
+        }
+
+        let mut dropped = 0;
+        let output = write_string(&[&buffer], &mut dropped);
+        assert_eq!(output.lines().count(), CAPACITY + 1);
+        assert_eq!(output.lines().last(), Some("rtic: log: 3 records dropped"));
+        assert_eq!(dropped, 3);
+
+        // Only new drops are reported
+        buffer.push(record(0, 0));
+        assert!(!write_string(&[&buffer], &mut dropped).contains("records dropped"));
+        for i in 0..CAPACITY as u64 + 2 {
+            buffer.push(record(i, i));
+        }
+        let output = write_string(&[&buffer], &mut dropped);
+        assert_eq!(output.lines().last(), Some("rtic: log: 2 records dropped"));
+        assert_eq!(dropped, 5);
+    }
+
+    #[test]
+    fn format_specs() {
+        crate::error!(
+            "{:>5}|{:#06x}|{:+.2}|{:e}|{:?}|{:<6}|{:?}",
+            42u32,
+            255u8,
+            1.5f64,
+            1500.0f64,
+            Duration::from_millis(1500),
+            true,
+            "ok",
+        );
+        crate::error!("{}-{}-{}", 'c', -7i32, u64::MAX);
+
+        let buffers = BUFFERS.buffers();
+        let current = buffers
+            .iter()
+            .find(|buffer| buffer.tid == unsafe { libc::gettid() } as u64)
+            .unwrap();
+        let output = write_string(&[current], &mut 0);
+        assert_eq!(
+            messages(&output),
+            [
+                "   42|0x00ff|+1.50|1.5e3|1.5s|true  |\"ok\"",
+                "c--7-18446744073709551615",
+            ]
+        );
+        let line = output.lines().next().unwrap();
+        assert!(line.contains(" ERROR ["));
+        assert!(line.contains("] rtic::log::tests: "));
+    }
+
+    #[test]
+    fn ordered_by_timestamp() {
+        let a = buffer("a");
+        let b = buffer("b");
+        for (buffer, timestamp) in [(&a, 1), (&b, 2), (&a, 3), (&b, 3), (&b, 4), (&a, 6)] {
+            buffer.push(record(timestamp * 1000, timestamp));
+        }
+
+        let output = write_string(&[&a, &b], &mut 0);
+        let lines: Vec<_> = output
+            .lines()
+            .map(|line| {
+                let (_, thread) = line.split_once('[').unwrap();
+                let (thread, message) = thread.split_once("] test: ").unwrap();
+                format!("{} {}", thread, message)
+            })
+            .collect();
+        // Equal timestamps keep the order of the buffers
+        assert_eq!(lines, ["a 1", "b 2", "a 3", "b 3", "b 4", "a 6"]);
+        assert!(output.starts_with("    0.000001 INFO  [a] test: 1\n"));
+    }
+}
@@ -0,0 +1,110 @@
+//! Memory locking and prefaulting
+//!
+//! A page fault inside a task stalls the thread until the kernel has mapped the page, which is a
+//! major source of jitter. Locking the address space and touching the stack and heap at startup
+//! moves these faults out of the tasks.
+
+use std::{io, mem::MaybeUninit};
+
+/// Locks current and future mappings of the process in memory
+///
+/// Also configures malloc to never return memory to the kernel, see [`configure_malloc`].
+pub fn lock() -> io::Result<()> {
+    configure_malloc();
+
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Disables heap trimming and `mmap` based allocations
+///
+/// Freed memory stays in the heap, so that pages which were faulted in (or locked) once are
+/// reused by later allocations.
+pub fn configure_malloc() {
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    unsafe {
+        libc::mallopt(libc::M_TRIM_THRESHOLD, -1);
+        libc::mallopt(libc::M_MMAP_MAX, 0);
+    }
+}
+
+/// Faults in `size` bytes of heap memory
+///
+/// Memory is allocated, touched and freed. It stays in the heap for later allocations as long as
+/// trimming is disabled by [`configure_malloc`].
+pub fn prefault_heap(size: usize) {
+    configure_malloc();
+
+    let mut heap = Vec::<u8>::with_capacity(size);
+    let ptr = heap.as_mut_ptr();
+    for offset in (0..size).step_by(page_size()) {
+        unsafe { ptr.add(offset).write_volatile(0) };
+    }
+    std::hint::black_box(&heap);
+}
+
+/// Stack that must stay untouched below the prefaulted part, for the frames of the code that runs
+/// while prefaulting and the guard page
+pub const STACK_MARGIN: usize = 64 * 1024;
+
+/// Faults in `SIZE` bytes of the current thread's stack
+///
+/// Nothing is touched if `SIZE` plus [`STACK_MARGIN`] does not fit in the remaining stack of the
+/// thread, which is reported instead. The size of the main thread stack is only known at runtime,
+/// it is set by `ulimit -s`.
+pub fn prefault_stack<const SIZE: usize>() {
+    match remaining_stack() {
+        Some(remaining) if SIZE + STACK_MARGIN > remaining => {
+            eprintln!(
+                "rtic: not prefaulting {} bytes of stack, only {} bytes are left in thread {}",
+                SIZE,
+                remaining,
+                std::thread::current().name().unwrap_or("<unnamed>")
+            );
+        }
+        _ => touch_stack::<SIZE>(),
+    }
+}
+
+#[inline(never)]
+fn touch_stack<const SIZE: usize>() {
+    let mut stack = MaybeUninit::<[u8; SIZE]>::uninit();
+    let ptr = stack.as_mut_ptr() as *mut u8;
+    // Stack grows down, start next to the current frame
+    for offset in (0..SIZE).step_by(page_size()).rev() {
+        unsafe { ptr.add(offset).write_volatile(0) };
+    }
+    std::hint::black_box(&stack);
+}
+
+/// Returns the number of bytes between the current frame and the end of the thread's stack
+fn remaining_stack() -> Option<usize> {
+    let mut attr = MaybeUninit::<libc::pthread_attr_t>::uninit();
+    let mut addr = std::ptr::null_mut();
+    let mut size = 0;
+
+    unsafe {
+        if libc::pthread_getattr_np(libc::pthread_self(), attr.as_mut_ptr()) != 0 {
+            return None;
+        }
+        let result = libc::pthread_attr_getstack(attr.as_ptr(), &mut addr, &mut size);
+        libc::pthread_attr_destroy(attr.as_mut_ptr());
+        if result != 0 {
+            return None;
+        }
+    }
+
+    // Stack grows down from `addr + size`
+    let current = &attr as *const _ as usize;
+    Some(current.saturating_sub(addr as usize))
+}
+
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
@@ -0,0 +1,793 @@
+//! Prometheus and OpenMetrics exposition
+//!
+//! `#[rtic::app(metrics = "127.0.0.1:9100")]` starts a thread that serves the statistics of the
+//! application over HTTP at `/metrics`, in the Prometheus text format or in OpenMetrics if the
+//! scraper asks for it. The address is a localhost TCP port or the path of a Unix socket, i.e.
+//! `metrics = "/run/app/metrics.sock"`. In library mode it is overridden with `rtic::Config`.
+//!
+//! The thread runs with `SCHED_OTHER` and only reads counters that tasks update anyway, so a scrape
+//! does not delay the real-time threads. Served metrics:
+//!
+//! - `rtic_dispatcher_runs_total`, `rtic_dispatcher_queued`, `rtic_dispatcher_scheduled` and
+//!   `rtic_dispatcher_alive` of each priority level
+//! - `rtic_task_queue_used`, `rtic_task_queue_capacity`, `rtic_task_queue_high_water` and
+//!   `rtic_task_spawn_failures_total` of each task input queue
+//! - `rtic_task_deadline_misses_total` and `rtic_task_deadline_skips_total` of tasks with a
+//!   `deadline`
+//! - with the `stats` feature, `rtic_task_runs_total` and summaries of task latency, execution
+//!   time and CPU time, and of lock wait and hold times of each shared resource
+
+use std::{
+    fmt::{self, Write as _},
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{introspect::Snapshot, runtime::Stats, stats::Histogram};
+
+/// Address the metrics are served on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Localhost TCP address, i.e. `127.0.0.1:9100`
+    Tcp(SocketAddr),
+    /// Path of a Unix socket, which is replaced if it exists
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    /// Parses an absolute socket path or a loopback `ip:port`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('/') {
+            return Ok(Endpoint::Unix(s.into()));
+        }
+
+        match s.parse::<SocketAddr>() {
+            Ok(addr) if addr.ip().is_loopback() => Ok(Endpoint::Tcp(addr)),
+            Ok(_) => Err(format!("{} is not a localhost address", s)),
+            Err(_) => Err(format!(
+                "{} is neither an absolute socket path nor an `ip:port` address",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{}", addr),
+            Endpoint::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Time allowed to a scraper to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+static SERVER: Mutex<Option<(Endpoint, JoinHandle<()>)>> = Mutex::new(None);
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Starts the thread that serves the metrics
+///
+/// Called by the generated code once all dispatcher threads are spawned. Returns an error if the
+/// endpoint can not be bound or the thread not spawned.
+#[doc(hidden)]
+pub fn serve(
+    name: &str,
+    endpoint: Endpoint,
+    stats: fn() -> Stats,
+    introspect: fn() -> Snapshot,
+) -> io::Result<()> {
+    let (listener, endpoint) = Listener::bind(&endpoint).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to listen on {}: {}", endpoint, err),
+        )
+    })?;
+
+    let result = crate::runtime::spawn_thread(name, None, move || {
+        crate::sched::init_thread(0);
+
+        while let Ok(stream) = listener.accept() {
+            if STOP.load(Ordering::Relaxed) {
+                break;
+            }
+            // A scraper that does not send its request only fails its own scrape
+            let result = match &stream {
+                Stream::Tcp(stream) => stream
+                    .set_read_timeout(Some(REQUEST_TIMEOUT))
+                    .and_then(|_| respond(stream, stats, introspect)),
+                Stream::Unix(stream) => stream
+                    .set_read_timeout(Some(REQUEST_TIMEOUT))
+                    .and_then(|_| respond(stream, stats, introspect)),
+            };
+            if let Err(err) = result {
+                eprintln!("rtic: metrics: failed to respond: {}", err);
+            }
+        }
+    });
+    match result {
+        Ok(handle) => {
+            *SERVER.lock().unwrap() = Some((endpoint, handle));
+            Ok(())
+        }
+        Err(err) => {
+            if let Endpoint::Unix(path) = endpoint {
+                fs::remove_file(path).ok();
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Stops serving and removes the Unix socket
+///
+/// Called when the application stops.
+#[doc(hidden)]
+pub fn stop() {
+    if let Some((endpoint, handle)) = SERVER.lock().unwrap().take() {
+        STOP.store(true, Ordering::Relaxed);
+        // Wakes up the blocking `accept`, on the bound address
+        let _ = match &endpoint {
+            Endpoint::Tcp(addr) => TcpStream::connect(addr).map(drop),
+            Endpoint::Unix(path) => UnixStream::connect(path).map(drop),
+        };
+        handle.join().ok();
+        if let Endpoint::Unix(path) = endpoint {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Binds the endpoint and returns the bound address, i.e. the port chosen for port 0
+    fn bind(endpoint: &Endpoint) -> io::Result<(Self, Endpoint)> {
+        match endpoint {
+            Endpoint::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                let addr = listener.local_addr()?;
+                Ok((Listener::Tcp(listener), Endpoint::Tcp(addr)))
+            }
+            Endpoint::Unix(path) => {
+                // Left behind by a previous run that did not stop cleanly
+                if UnixStream::connect(path).is_err() {
+                    fs::remove_file(path).ok();
+                }
+                let listener = UnixListener::bind(path)?;
+                Ok((Listener::Unix(listener), endpoint.clone()))
+            }
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+/// Reads an HTTP request and writes the metrics or an error
+fn respond<S>(stream: S, stats: fn() -> Stats, introspect: fn() -> Snapshot) -> io::Result<()>
+where
+    S: Read + Write,
+{
+    let mut reader = BufReader::new(stream);
+    let request = read_request(&mut reader)?;
+
+    let writer = reader.get_mut();
+    match request {
+        Ok(format) => {
+            let body = render(&stats(), &introspect(), format);
+            write!(
+                writer,
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                format.content_type(),
+                body.len(),
+                body
+            )?;
+        }
+        Err(status) => write!(
+            writer,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+            status,
+            status.len() + 1,
+            status
+        )?,
+    }
+    writer.flush()
+}
+
+/// Reads the request line and headers
+///
+/// Returns the format asked for by the `Accept` header, or the status line of an error response.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Result<Format, &'static str>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let request = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) if version.starts_with("HTTP/") => {
+            let path = target.split('?').next().unwrap_or_default();
+            if method != "GET" {
+                Err("405 Method Not Allowed")
+            } else if path != "/metrics" && path != "/" {
+                Err("404 Not Found")
+            } else {
+                Ok(())
+            }
+        }
+        _ => Err("400 Bad Request"),
+    };
+
+    let mut openmetrics = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let lower = line.to_ascii_lowercase();
+        if lower.starts_with("accept:") && lower.contains("application/openmetrics-text") {
+            openmetrics = true;
+        }
+    }
+
+    Ok(request.map(|()| {
+        if openmetrics {
+            Format::OpenMetrics
+        } else {
+            Format::Prometheus
+        }
+    }))
+}
+
+/// Exposition format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Prometheus text format 0.0.4
+    Prometheus,
+    /// OpenMetrics 1.0 text format
+    OpenMetrics,
+}
+
+impl Format {
+    /// Value of the `Content-Type` header
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+/// Renders the statistics of an application, i.e. to serve them from an existing HTTP server
+pub fn render(stats: &Stats, snapshot: &Snapshot, format: Format) -> String {
+    let mut out = Writer {
+        out: String::new(),
+        format,
+    };
+
+    out.family(
+        "rtic_dispatcher_runs",
+        "counter",
+        "Tasks run by the dispatcher",
+    );
+    for level in &snapshot.levels {
+        out.sample(
+            "rtic_dispatcher_runs_total",
+            &[("priority", &level.priority)],
+            level.dispatched,
+        );
+    }
+    out.family(
+        "rtic_dispatcher_queued",
+        "gauge",
+        "Tasks waiting in the run queue",
+    );
+    for level in &snapshot.levels {
+        out.sample(
+            "rtic_dispatcher_queued",
+            &[("priority", &level.priority)],
+            level.queued,
+        );
+    }
+    out.family(
+        "rtic_dispatcher_scheduled",
+        "gauge",
+        "Tasks scheduled for a later instant",
+    );
+    for level in &snapshot.levels {
+        out.sample(
+            "rtic_dispatcher_scheduled",
+            &[("priority", &level.priority)],
+            level.scheduled,
+        );
+    }
+    out.family(
+        "rtic_dispatcher_alive",
+        "gauge",
+        "Whether the dispatcher thread is running",
+    );
+    for level in &snapshot.levels {
+        out.sample(
+            "rtic_dispatcher_alive",
+            &[("priority", &level.priority)],
+            level.alive as u8,
+        );
+    }
+
+    out.family(
+        "rtic_task_queue_used",
+        "gauge",
+        "Spawned instances that have not started",
+    );
+    for task in &snapshot.tasks {
+        out.sample("rtic_task_queue_used", &[("task", &task.name)], task.used);
+    }
+    out.family(
+        "rtic_task_queue_capacity",
+        "gauge",
+        "Capacity of the task input queue",
+    );
+    for task in &snapshot.tasks {
+        out.sample(
+            "rtic_task_queue_capacity",
+            &[("task", &task.name)],
+            task.capacity,
+        );
+    }
+    out.family(
+        "rtic_task_queue_high_water",
+        "gauge",
+        "Highest number of spawned instances that have not started",
+    );
+    for task in &snapshot.tasks {
+        out.sample(
+            "rtic_task_queue_high_water",
+            &[("task", &task.name)],
+            task.high_water,
+        );
+    }
+    out.family(
+        "rtic_task_spawn_failures",
+        "counter",
+        "Spawns that failed because the task was at capacity",
+    );
+    for task in &snapshot.tasks {
+        out.sample(
+            "rtic_task_spawn_failures_total",
+            &[("task", &task.name)],
+            task.spawn_failures,
+        );
+    }
+
+    if !stats.deadlines.is_empty() {
+        out.family(
+            "rtic_task_deadline_misses",
+            "counter",
+            "Runs that completed after the deadline",
+        );
+        for deadline in &stats.deadlines {
+            out.sample(
+                "rtic_task_deadline_misses_total",
+                &[("task", &deadline.name)],
+                deadline.missed(),
+            );
+        }
+        out.family(
+            "rtic_task_deadline_skips",
+            "counter",
+            "Instances skipped because the deadline had passed",
+        );
+        for deadline in &stats.deadlines {
+            out.sample(
+                "rtic_task_deadline_skips_total",
+                &[("task", &deadline.name)],
+                deadline.skipped(),
+            );
+        }
+    }
+
+    if crate::stats::ENABLED {
+        out.family("rtic_task_runs", "counter", "Completed task runs");
+        for task in &stats.tasks {
+            out.sample(
+                "rtic_task_runs_total",
+                &[("task", &task.name)],
+                task.wall_time.count(),
+            );
+        }
+
+        out.family(
+            "rtic_task_latency_seconds",
+            "summary",
+            "Time from release until the task starts",
+        );
+        for task in &stats.tasks {
+            out.summary(
+                "rtic_task_latency_seconds",
+                ("task", task.name),
+                &task.latency,
+            );
+        }
+        out.family(
+            "rtic_task_execution_seconds",
+            "summary",
+            "Execution time measured with the monotonic clock",
+        );
+        for task in &stats.tasks {
+            out.summary(
+                "rtic_task_execution_seconds",
+                ("task", task.name),
+                &task.wall_time,
+            );
+        }
+        out.family(
+            "rtic_task_cpu_seconds",
+            "summary",
+            "Execution time measured with the thread CPU clock",
+        );
+        for task in &stats.tasks {
+            out.summary("rtic_task_cpu_seconds", ("task", task.name), &task.cpu_time);
+        }
+
+        out.family(
+            "rtic_lock_wait_seconds",
+            "summary",
+            "Time until the shared resource is acquired",
+        );
+        for lock in &stats.locks {
+            out.summary(
+                "rtic_lock_wait_seconds",
+                ("resource", lock.name),
+                &lock.wait,
+            );
+        }
+        out.family(
+            "rtic_lock_held_seconds",
+            "summary",
+            "Time the shared resource is held",
+        );
+        for lock in &stats.locks {
+            out.summary(
+                "rtic_lock_held_seconds",
+                ("resource", lock.name),
+                &lock.held,
+            );
+        }
+    }
+
+    if format == Format::OpenMetrics {
+        out.out.push_str("# EOF\n");
+    }
+    out.out
+}
+
+struct Writer {
+    out: String,
+    format: Format,
+}
+
+impl Writer {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        // Prometheus names counters by their samples, OpenMetrics without the `_total` suffix
+        let suffix = match (self.format, kind) {
+            (Format::Prometheus, "counter") => "_total",
+            _ => "",
+        };
+        let _ = writeln!(self.out, "# HELP {}{} {}", name, suffix, help);
+        let _ = writeln!(self.out, "# TYPE {}{} {}", name, suffix, kind);
+    }
+
+    fn sample(
+        &mut self,
+        name: &str,
+        labels: &[(&str, &dyn fmt::Display)],
+        value: impl fmt::Display,
+    ) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (i, (label, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                // Label values are escaped the same way in both formats
+                let value = value
+                    .to_string()
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n");
+                let _ = write!(self.out, "{}=\"{}\"", label, value);
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    fn summary(&mut self, name: &str, (label, value): (&str, &str), histogram: &Histogram) {
+        for quantile in [0.5, 0.9, 0.99] {
+            let seconds = histogram.percentile(quantile).as_secs_f64();
+            self.sample(name, &[(label, &value), ("quantile", &quantile)], seconds);
+        }
+        let sum = histogram.sum().as_secs_f64();
+        self.sample(&format!("{}_sum", name), &[(label, &value)], sum);
+        self.sample(
+            &format!("{}_count", name),
+            &[(label, &value)],
+            histogram.count(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        deadline::TaskDeadline,
+        introspect::{Level, Task},
+        stats::LockStats,
+    };
+    use std::io::Cursor;
+
+    static DEADLINE: TaskDeadline = TaskDeadline::new("tick", 2, Duration::from_millis(10), false);
+
+    fn stats() -> Stats {
+        let lock: &'static LockStats = Box::leak(Box::new(LockStats::new("shared")));
+        lock.wait.record(Duration::from_micros(3));
+        lock.held.record(Duration::from_micros(40));
+
+        Stats {
+            threads: vec![],
+            tasks: vec![],
+            deadlines: vec![&DEADLINE],
+            locks: vec![lock],
+        }
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            levels: vec![Level {
+                priority: 2,
+                thread: "app-2",
+                tid: 100,
+                queued: 1,
+                scheduled: 3,
+                running: None,
+                dispatched: 42,
+                alive: true,
+            }],
+            tasks: vec![Task {
+                name: "tick",
+                priority: 2,
+                used: 1,
+                capacity: 4,
+                high_water: 2,
+                spawn_failures: 5,
+            }],
+        }
+    }
+
+    /// Expected output, `%t` is the `_total` suffix of counter families in the Prometheus format
+    const COMMON: &str = r#"# HELP rtic_dispatcher_runs%t Tasks run by the dispatcher
+# TYPE rtic_dispatcher_runs%t counter
+rtic_dispatcher_runs_total{priority="2"} 42
+# HELP rtic_dispatcher_queued Tasks waiting in the run queue
+# TYPE rtic_dispatcher_queued gauge
+rtic_dispatcher_queued{priority="2"} 1
+# HELP rtic_dispatcher_scheduled Tasks scheduled for a later instant
+# TYPE rtic_dispatcher_scheduled gauge
+rtic_dispatcher_scheduled{priority="2"} 3
+# HELP rtic_dispatcher_alive Whether the dispatcher thread is running
+# TYPE rtic_dispatcher_alive gauge
+rtic_dispatcher_alive{priority="2"} 1
+# HELP rtic_task_queue_used Spawned instances that have not started
+# TYPE rtic_task_queue_used gauge
+rtic_task_queue_used{task="tick"} 1
+# HELP rtic_task_queue_capacity Capacity of the task input queue
+# TYPE rtic_task_queue_capacity gauge
+rtic_task_queue_capacity{task="tick"} 4
+# HELP rtic_task_queue_high_water Highest number of spawned instances that have not started
+# TYPE rtic_task_queue_high_water gauge
+rtic_task_queue_high_water{task="tick"} 2
+# HELP rtic_task_spawn_failures%t Spawns that failed because the task was at capacity
+# TYPE rtic_task_spawn_failures%t counter
+rtic_task_spawn_failures_total{task="tick"} 5
+# HELP rtic_task_deadline_misses%t Runs that completed after the deadline
+# TYPE rtic_task_deadline_misses%t counter
+rtic_task_deadline_misses_total{task="tick"} 0
+# HELP rtic_task_deadline_skips%t Instances skipped because the deadline had passed
+# TYPE rtic_task_deadline_skips%t counter
+rtic_task_deadline_skips_total{task="tick"} 0
+"#;
+
+    /// Expected output of the `stats` feature
+    const STATS: &str = r#"# HELP rtic_task_runs%t Completed task runs
+# TYPE rtic_task_runs%t counter
+# HELP rtic_task_latency_seconds Time from release until the task starts
+# TYPE rtic_task_latency_seconds summary
+# HELP rtic_task_execution_seconds Execution time measured with the monotonic clock
+# TYPE rtic_task_execution_seconds summary
+# HELP rtic_task_cpu_seconds Execution time measured with the thread CPU clock
+# TYPE rtic_task_cpu_seconds summary
+# HELP rtic_lock_wait_seconds Time until the shared resource is acquired
+# TYPE rtic_lock_wait_seconds summary
+rtic_lock_wait_seconds{resource="shared",quantile="0.5"} 0.000003
+rtic_lock_wait_seconds{resource="shared",quantile="0.9"} 0.000003
+rtic_lock_wait_seconds{resource="shared",quantile="0.99"} 0.000003
+rtic_lock_wait_seconds_sum{resource="shared"} 0.000003
+rtic_lock_wait_seconds_count{resource="shared"} 1
+# HELP rtic_lock_held_seconds Time the shared resource is held
+# TYPE rtic_lock_held_seconds summary
+rtic_lock_held_seconds{resource="shared",quantile="0.5"} 0.00004
+rtic_lock_held_seconds{resource="shared",quantile="0.9"} 0.00004
+rtic_lock_held_seconds{resource="shared",quantile="0.99"} 0.00004
+rtic_lock_held_seconds_sum{resource="shared"} 0.00004
+rtic_lock_held_seconds_count{resource="shared"} 1
+"#;
+
+    fn expected(total: &str, eof: &str) -> String {
+        let stats = if crate::stats::ENABLED { STATS } else { "" };
+        format!("{}{}{}", COMMON, stats, eof).replace("%t", total)
+    }
+
+    #[test]
+    fn render_prometheus() {
+        let out = render(&stats(), &snapshot(), Format::Prometheus);
+        assert_eq!(out, expected("_total", ""));
+    }
+
+    #[test]
+    fn render_openmetrics() {
+        let out = render(&stats(), &snapshot(), Format::OpenMetrics);
+        assert_eq!(out, expected("", "# EOF\n"));
+    }
+
+    #[test]
+    fn escape_label_values() {
+        let mut snapshot = snapshot();
+        snapshot.tasks[0].name = "a\"b\\c\nd";
+
+        let out = render(&stats(), &snapshot, Format::Prometheus);
+        assert!(out.contains("rtic_task_queue_used{task=\"a\\\"b\\\\c\\nd\"} 1\n"));
+    }
+
+    fn request(request: &str) -> Result<Format, &'static str> {
+        read_request(&mut Cursor::new(request)).unwrap()
+    }
+
+    #[test]
+    fn parse_request() {
+        assert_eq!(
+            request("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            Ok(Format::Prometheus)
+        );
+        assert_eq!(
+            request("GET /?x=1 HTTP/1.0\r\n\r\n"),
+            Ok(Format::Prometheus)
+        );
+        assert_eq!(
+            request("GET /metrics HTTP/1.1\r\nACCEPT: application/openmetrics-text; version=1.0.0\r\n\r\n"),
+            Ok(Format::OpenMetrics)
+        );
+
+        assert_eq!(request("GET /other HTTP/1.1\r\n\r\n"), Err("404 Not Found"));
+        assert_eq!(
+            request("POST /metrics HTTP/1.1\r\n\r\n"),
+            Err("405 Method Not Allowed")
+        );
+        assert_eq!(request("GET\r\n\r\n"), Err("400 Bad Request"));
+        assert_eq!(request("GET / SSH-2.0\r\n\r\n"), Err("400 Bad Request"));
+        assert_eq!(request(""), Err("400 Bad Request"));
+    }
+
+    /// In-memory connection
+    struct Connection {
+        request: Cursor<&'static str>,
+        response: Vec<u8>,
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.request.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.response.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn response(request: &'static str) -> String {
+        let mut connection = Connection {
+            request: Cursor::new(request),
+            response: vec![],
+        };
+        respond(&mut connection, stats, snapshot).unwrap();
+        String::from_utf8(connection.response).unwrap()
+    }
+
+    #[test]
+    fn respond_metrics() {
+        let body = expected("", "# EOF\n");
+        assert_eq!(
+            response("GET /metrics HTTP/1.1\r\nAccept: application/openmetrics-text\r\n\r\n"),
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        );
+    }
+
+    #[test]
+    fn respond_error() {
+        assert_eq!(
+            response("GET /favicon.ico HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 14\r\nConnection: close\r\n\r\n404 Not Found\n"
+        );
+        assert!(response("\r\n").starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn serve_and_stop() {
+        let endpoint = |addr: &str| addr.parse::<Endpoint>().unwrap();
+
+        // Bind failures are returned
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let err = serve(
+            "metrics",
+            Endpoint::Tcp(taken.local_addr().unwrap()),
+            stats,
+            snapshot,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert!(err
+            .to_string()
+            .starts_with("failed to listen on 127.0.0.1:"));
+
+        // Port 0 is replaced with the bound port, which `stop` connects to
+        serve("metrics", endpoint("127.0.0.1:0"), stats, snapshot).unwrap();
+        let addr = match &SERVER.lock().unwrap().as_ref().unwrap().0 {
+            Endpoint::Tcp(addr) => *addr,
+            Endpoint::Unix(_) => unreachable!(),
+        };
+        assert_ne!(addr.port(), 0);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        stop();
+        assert!(SERVER.lock().unwrap().is_none());
+    }
+}
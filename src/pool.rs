@@ -0,0 +1,115 @@
+//! Fixed capacity lock-free object pool
+//!
+//! Built on [`crate::slab`], but objects are returned to the pool by dropping their handle on any
+//! thread, which makes it suitable for passing large buffers between tasks of different priorities
+//! without allocating. The slab keeps its free slots in a lock-free stack, which any number of
+//! threads can take slots from and return them to.
+//!
+//! ```
+//! use rtic::pool::Pool;
+//!
+//! rtic::lazy_static::lazy_static! {
+//!     static ref BUFFERS: Pool<[u8; 4096], 4> = Pool::new();
+//! }
+//!
+//! let mut buf = BUFFERS.alloc([0; 4096]).unwrap();
+//! buf[0] = 1;
+//! assert_eq!(BUFFERS.available(), 3);
+//!
+//! // Returns the slot back to the pool
+//! drop(buf);
+//! assert_eq!(BUFFERS.available(), 4);
+//! ```
+
+use crate::slab::{Slab, SlabHandle};
+use std::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+
+/// Fixed capacity object pool
+pub struct Pool<T, const N: usize> {
+    slab: Slab<T, N>,
+}
+
+impl<T, const N: usize> Pool<T, N> {
+    /// Creates an empty pool with capacity `N`
+    pub fn new() -> Self {
+        Self { slab: Slab::new() }
+    }
+
+    /// Moves `value` into the pool. Returns `value` back if the pool is exhausted.
+    pub fn alloc(&'static self, value: T) -> Result<Box<T, N>, T> {
+        let handle = self.slab.insert(value)?;
+
+        Ok(Box {
+            pool: self,
+            handle: ManuallyDrop::new(handle),
+        })
+    }
+
+    /// Returns the number of free slots
+    pub fn available(&self) -> usize {
+        N - self.slab.used()
+    }
+
+    /// Returns the capacity of the pool
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<T, const N: usize> Default for Pool<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Owning handle to a value stored in the [`Pool`]
+///
+/// Value is dropped and the slot is returned to the pool when the handle is dropped.
+pub struct Box<T: 'static, const N: usize> {
+    pool: &'static Pool<T, N>,
+    // Taken out only when the box is consumed
+    handle: ManuallyDrop<SlabHandle>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Box<T, N> {}
+unsafe impl<T: Sync, const N: usize> Sync for Box<T, N> {}
+
+impl<T, const N: usize> Box<T, N> {
+    /// Moves the value out of the pool and frees the slot
+    pub fn into_inner(this: Self) -> T {
+        let mut this = ManuallyDrop::new(this);
+        let handle = unsafe { ManuallyDrop::take(&mut this.handle) };
+        this.pool.slab.remove(handle)
+    }
+}
+
+impl<T, const N: usize> Deref for Box<T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.pool.slab.get(&self.handle) }
+    }
+}
+
+impl<T, const N: usize> DerefMut for Box<T, N> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.pool.slab.get(&self.handle) }
+    }
+}
+
+impl<T, const N: usize> Drop for Box<T, N> {
+    fn drop(&mut self) {
+        let handle = unsafe { ManuallyDrop::take(&mut self.handle) };
+        drop(self.pool.slab.remove(handle));
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for Box<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
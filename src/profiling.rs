@@ -0,0 +1,194 @@
+//! Profiling with `tracing`
+//!
+//! With the `profiling` feature of this crate, task executions, spawns and locks are recorded as
+//! `tracing` spans and events. The generated `main` installs a subscriber that writes a Chrome trace
+//! (`chrome://tracing`, [ui.perfetto.dev](https://ui.perfetto.dev)) and prints events filtered by
+//! `RUST_LOG`. The setup is changed by returning a [`Profiling`] from the function marked
+//! `#[profiling]` in the app module:
+//!
+//! ```ignore
+//! #[profiling]
+//! fn profiling() -> rtic::profiling::Profiling {
+//!     rtic::profiling::Profiling::new().path("app.json").fmt(false)
+//! }
+//! ```
+//!
+//! In library mode [`init`] is called by the user before `app::start`, or any subscriber is
+//! installed instead. Without the feature nothing is recorded, the instrumentation compiles to
+//! nothing and [`Profiling`] is ignored.
+
+#[cfg(feature = "profiling")]
+use std::sync::Mutex;
+
+#[cfg(feature = "profiling")]
+use tracing_subscriber::{prelude::*, util::SubscriberInitExt, Registry};
+
+/// Whether spans and events are recorded
+pub const ENABLED: bool = cfg!(feature = "profiling");
+
+/// Additional layer of the subscriber
+#[cfg(feature = "profiling")]
+pub type Layer = Box<dyn tracing_subscriber::Layer<Registry> + Send + Sync>;
+
+/// Subscriber setup
+pub struct Profiling {
+    path: Option<String>,
+    chrome: bool,
+    fmt: bool,
+    #[cfg(feature = "profiling")]
+    layers: Vec<Layer>,
+    #[cfg(feature = "profiling")]
+    subscriber: Option<tracing::Dispatch>,
+}
+
+impl Profiling {
+    /// Chrome trace in the working directory and events printed to stderr
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            chrome: true,
+            fmt: true,
+            #[cfg(feature = "profiling")]
+            layers: Vec::new(),
+            #[cfg(feature = "profiling")]
+            subscriber: None,
+        }
+    }
+
+    /// Sets the path of the Chrome trace, by default `trace-<timestamp>.json`
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Enables or disables the Chrome trace
+    pub fn chrome(mut self, enabled: bool) -> Self {
+        self.chrome = enabled;
+        self
+    }
+
+    /// Enables or disables printing of events filtered by `RUST_LOG`
+    pub fn fmt(mut self, enabled: bool) -> Self {
+        self.fmt = enabled;
+        self
+    }
+
+    /// Adds a layer to the subscriber
+    #[cfg(feature = "profiling")]
+    pub fn layer(mut self, layer: impl tracing_subscriber::Layer<Registry> + Send + Sync) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Replaces the whole setup with a subscriber
+    #[cfg(feature = "profiling")]
+    pub fn subscriber(mut self, subscriber: impl Into<tracing::Dispatch>) -> Self {
+        self.subscriber = Some(subscriber.into());
+        self
+    }
+}
+
+impl Default for Profiling {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes the rest of the Chrome trace when dropped
+#[cfg(feature = "profiling")]
+static CHROME: Mutex<Option<tracing_chrome::FlushGuard>> = Mutex::new(None);
+
+/// Installs the global subscriber
+///
+/// Called by the generated `main`. The Chrome trace is completed when the application stops.
+pub fn init(profiling: Profiling) {
+    #[cfg(feature = "profiling")]
+    {
+        if let Some(subscriber) = profiling.subscriber {
+            if tracing::dispatcher::set_global_default(subscriber).is_err() {
+                eprintln!("rtic: profiling: a subscriber is already installed");
+            }
+            return;
+        }
+
+        let mut layers = profiling.layers;
+        if profiling.chrome {
+            let mut builder = tracing_chrome::ChromeLayerBuilder::new();
+            if let Some(path) = profiling.path {
+                builder = builder.file(path);
+            }
+            let (layer, guard) = builder.build();
+            layers.push(Box::new(layer));
+            *CHROME.lock().unwrap() = Some(guard);
+        }
+        if profiling.fmt {
+            layers.push(Box::new(
+                tracing_subscriber::fmt::layer()
+                    .with_target(false)
+                    .with_filter(tracing_subscriber::EnvFilter::from_default_env()),
+            ));
+        }
+
+        if tracing_subscriber::registry()
+            .with(layers)
+            .try_init()
+            .is_err()
+        {
+            eprintln!("rtic: profiling: a subscriber is already installed");
+        }
+    }
+
+    #[cfg(not(feature = "profiling"))]
+    let _ = profiling;
+}
+
+/// Completes the Chrome trace
+///
+/// Called when the application stops.
+#[doc(hidden)]
+pub fn flush() {
+    #[cfg(feature = "profiling")]
+    drop(CHROME.lock().unwrap().take());
+}
+
+/// Enters a span until the end of the scope, does nothing without the `profiling` feature
+#[cfg(feature = "profiling")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __profiling_span {
+    ($name:expr) => {
+        $crate::tracing::span!($crate::tracing::Level::TRACE, $name).entered()
+    };
+}
+
+/// Enters a span until the end of the scope, does nothing without the `profiling` feature
+#[cfg(not(feature = "profiling"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __profiling_span {
+    ($name:expr) => {
+        $crate::profiling::NoSpan
+    };
+}
+
+/// Records a `TRACE` event, does nothing without the `profiling` feature
+#[cfg(feature = "profiling")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __profiling_trace {
+    ($($arg:tt)*) => {
+        $crate::tracing::trace!($($arg)*)
+    };
+}
+
+/// Records a `TRACE` event, does nothing without the `profiling` feature
+#[cfg(not(feature = "profiling"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __profiling_trace {
+    ($($arg:tt)*) => {};
+}
+
+/// Span entered by `__profiling_span!` without the `profiling` feature
+#[doc(hidden)]
+pub struct NoSpan;
@@ -0,0 +1,258 @@
+//! Lock-free buffers of each thread, drained by a low priority writer thread
+//!
+//! Used by [`crate::trace`] and [`crate::log`]. Every thread records into its own single producer,
+//! single consumer ring buffer, which is allocated by [`crate::init_thread_state`] or with the
+//! first record of the thread. Once the thread exits, its buffer is returned to the writer one
+//! last time and freed after that drain.
+
+use std::{
+    cell::{OnceCell, UnsafeCell},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::LocalKey,
+};
+
+/// Single producer, single consumer ring buffer of a thread
+pub(crate) struct Ring<T> {
+    items: Box<[UnsafeCell<T>]>,
+    // Next position to write, only changed by the owning thread
+    head: AtomicUsize,
+    // Next position to read, only changed by the writer
+    tail: AtomicUsize,
+    dropped: AtomicU64,
+    // Set when the owning thread exits
+    retired: AtomicBool,
+    /// Unique among all buffers of a [`Rings`]
+    pub id: u64,
+    /// Name of the owning thread
+    pub name: String,
+    pub tid: u64,
+    pub priority: u8,
+}
+
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T: Copy> Ring<T> {
+    pub fn new(capacity: usize, empty: T, id: u64, name: String, tid: u64, priority: u8) -> Self {
+        Self {
+            items: (0..capacity).map(|_| UnsafeCell::new(empty)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+            retired: AtomicBool::new(false),
+            id,
+            name,
+            tid,
+            priority,
+        }
+    }
+
+    /// Appends an item, or counts it as dropped if the buffer is full. Only called by the owning
+    /// thread.
+    pub fn push(&self, item: T) {
+        let head = self.head.load(Ordering::Relaxed);
+        if head - self.tail.load(Ordering::Acquire) == self.items.len() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        unsafe { *self.items[head % self.items.len()].get() = item };
+        self.head.store(head + 1, Ordering::Release);
+    }
+
+    /// Calls `f` with all items in the order they were pushed and removes them. Only called by the
+    /// writer.
+    pub fn drain(&self, mut f: impl FnMut(&T)) {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        for pos in tail..head {
+            f(unsafe { &*self.items[pos % self.items.len()].get() });
+        }
+        self.tail.store(head, Ordering::Release);
+    }
+
+    /// Returns the number of items dropped because the buffer was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Buffer of the current thread, retired when the thread exits
+pub(crate) struct Local<T>(Arc<Ring<T>>);
+
+impl<T> Drop for Local<T> {
+    fn drop(&mut self) {
+        self.0.retired.store(true, Ordering::Release);
+    }
+}
+
+/// Buffers of all threads that recorded an item
+pub(crate) struct Rings<T: 'static> {
+    local: &'static LocalKey<OnceCell<Local<T>>>,
+    capacity: usize,
+    empty: T,
+    buffers: Mutex<Vec<Arc<Ring<T>>>>,
+    next_id: AtomicU64,
+    // Dropped items of freed buffers
+    retired_dropped: AtomicU64,
+}
+
+impl<T: Copy + Send> Rings<T> {
+    /// Buffers have room for `capacity` items and are filled with `empty` when allocated
+    pub const fn new(
+        local: &'static LocalKey<OnceCell<Local<T>>>,
+        capacity: usize,
+        empty: T,
+    ) -> Self {
+        Self {
+            local,
+            capacity,
+            empty,
+            buffers: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(0),
+            retired_dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Calls `f` with the buffer of the current thread, which is allocated on first use
+    ///
+    /// Nothing is recorded while the thread exits.
+    #[inline]
+    pub fn with(&self, priority: u8, f: impl FnOnce(&Ring<T>)) {
+        let _ = self.local.try_with(|cell| {
+            let local = cell.get_or_init(|| {
+                let thread = std::thread::current();
+                let ring = Arc::new(Ring::new(
+                    self.capacity,
+                    self.empty,
+                    self.next_id.fetch_add(1, Ordering::Relaxed),
+                    thread.name().unwrap_or("thread").to_owned(),
+                    unsafe { libc::gettid() } as u64,
+                    priority,
+                ));
+                self.buffers.lock().unwrap().push(ring.clone());
+                Local(ring)
+            });
+            f(&local.0);
+        });
+    }
+
+    /// Returns the buffers to drain, in order of allocation
+    ///
+    /// Buffers of exited threads are returned a last time and freed once the caller drops them.
+    pub fn buffers(&self) -> Vec<Arc<Ring<T>>> {
+        let mut buffers = self.buffers.lock().unwrap();
+        let current = buffers.clone();
+        buffers.retain(|ring| {
+            // Checked before the caller drains, so that it drains the last items of the thread
+            let retired = ring.retired.load(Ordering::Acquire);
+            if retired {
+                self.retired_dropped
+                    .fetch_add(ring.dropped(), Ordering::Relaxed);
+            }
+            !retired
+        });
+        current
+    }
+
+    /// Returns the number of items dropped by all threads so far
+    pub fn dropped(&self) -> u64 {
+        let buffers = self.buffers.lock().unwrap();
+        buffers.iter().map(|ring| ring.dropped()).sum::<u64>()
+            + self.retired_dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    thread_local! {
+        static LOCAL: OnceCell<Local<u64>> = const { OnceCell::new() };
+    }
+    static RINGS: Rings<u64> = Rings::new(&LOCAL, 4, 0);
+
+    fn ring(capacity: usize) -> Ring<u64> {
+        Ring::new(capacity, 0, 0, "test".to_owned(), 1, 0)
+    }
+
+    #[test]
+    fn overflow() {
+        let ring = ring(16);
+        for item in 0..19 {
+            ring.push(item);
+        }
+        assert_eq!(ring.dropped(), 3);
+
+        // Newest items are dropped
+        let mut items = vec![];
+        ring.drain(|&item| items.push(item));
+        assert!(items.iter().copied().eq(0..16));
+
+        // Drained space is reused
+        ring.push(7);
+        items.clear();
+        ring.drain(|&item| items.push(item));
+        assert_eq!(items, [7]);
+        assert_eq!(ring.dropped(), 3);
+    }
+
+    #[test]
+    fn concurrent() {
+        const ITEMS: u64 = 100_000;
+        let ring: &'static Ring<u64> = Box::leak(Box::new(ring(1 << 10)));
+
+        let producer = std::thread::spawn(move || {
+            for item in 0..ITEMS {
+                ring.push(item);
+            }
+        });
+
+        let mut items = vec![];
+        while !producer.is_finished() {
+            ring.drain(|&item| items.push(item));
+        }
+        producer.join().unwrap();
+        ring.drain(|&item| items.push(item));
+
+        // Items arrive in order, each either drained or counted as dropped
+        assert!(items.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(items.len() as u64 + ring.dropped(), ITEMS);
+    }
+
+    #[test]
+    fn retired_freed() {
+        let find = |buffers: &[Arc<Ring<u64>>]| {
+            buffers.iter().find(|ring| ring.name == "retired").cloned()
+        };
+
+        std::thread::Builder::new()
+            .name("retired".into())
+            .spawn(|| {
+                RINGS.with(3, |ring| {
+                    for item in 0..6 {
+                        ring.push(item);
+                    }
+                })
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        // Returned once more after the thread exited, with its last items
+        let ring = find(&RINGS.buffers()).unwrap();
+        assert_eq!(ring.priority, 3);
+        let mut items = vec![];
+        ring.drain(|&item| items.push(item));
+        assert_eq!(items, [0, 1, 2, 3]);
+
+        // Freed once the writer drops it, its drops are still counted
+        assert!(find(&RINGS.buffers()).is_none());
+        let weak = Arc::downgrade(&ring);
+        drop(ring);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(RINGS.dropped(), 2);
+    }
+}
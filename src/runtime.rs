@@ -0,0 +1,549 @@
+//! Control of applications started with `app::start`
+//!
+//! With `#[rtic::app(main = false)]` no `fn main` is generated and the application can be embedded
+//! in a larger program, a library or a test. `app::start` runs `#[init]`, spawns the dispatcher
+//! threads and returns an [`AppHandle`].
+
+use std::{
+    convert::Infallible,
+    fmt, io,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    deadline::TaskDeadline,
+    environment::{AuditError, Policy},
+    introspect::Snapshot,
+    metrics::Endpoint,
+    sched::Mode,
+    stats::{LockStats, TaskStats},
+};
+
+/// Runtime configuration of an application
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Scheduling mode, overrides command line and environment. See [`crate::sched`].
+    pub mode: Option<Mode>,
+    /// Startup audit policy, overrides the `audit` argument of `#[app]`
+    pub audit: Option<Policy>,
+    /// Address to serve metrics on, overrides the `metrics` argument of `#[app]`. See
+    /// [`crate::metrics`].
+    pub metrics: Option<Endpoint>,
+}
+
+/// Error returned by `app::run` and `app::start`
+///
+/// `E` is the error type of a fallible `#[init]`.
+#[derive(Debug)]
+pub enum Error<E = Infallible> {
+    /// The environment audit failed with [`Policy::Fail`]
+    Audit(AuditError),
+    /// `#[init]` returned an error
+    Init(E),
+    /// A thread of the application could not be spawned
+    Spawn(io::Error),
+    /// The metrics endpoint could not be bound or its thread not spawned
+    Metrics(io::Error),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Audit(err) => err.fmt(f),
+            Error::Init(err) => write!(f, "init failed: {}", err),
+            Error::Spawn(err) => err.fmt(f),
+            Error::Metrics(err) => write!(f, "metrics: {}", err),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Audit(err) => Some(err),
+            Error::Init(err) => Some(err),
+            Error::Spawn(err) => Some(err),
+            Error::Metrics(err) => Some(err),
+        }
+    }
+}
+
+/// Statistics of a running application
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// Dispatcher threads, ordered by priority
+    pub threads: Vec<ThreadStats>,
+    /// Timing of each task, only collected with the `stats` feature
+    pub tasks: Vec<&'static TaskStats>,
+    /// Deadline misses of tasks with a `deadline`
+    pub deadlines: Vec<&'static TaskDeadline>,
+    /// Lock timing of each shared resource, only collected with the `stats` feature
+    pub locks: Vec<&'static LockStats>,
+}
+
+/// Statistics of a dispatcher thread
+#[derive(Debug, Clone)]
+pub struct ThreadStats {
+    /// OS thread name
+    pub name: &'static str,
+    /// Priority of the thread
+    pub priority: u8,
+    /// Number of tasks run by the thread
+    pub dispatched: u64,
+}
+
+/// Progress of a dispatcher thread, used to detect stalled threads
+#[derive(Debug, Default)]
+pub struct Progress {
+    started: AtomicU64,
+    finished: AtomicU64,
+    // Tasks spawned for immediate execution and received by the dispatcher
+    released: AtomicU64,
+    received: AtomicU64,
+    // Tasks spawned for a later instant and received by the dispatcher
+    scheduled: AtomicU64,
+    received_scheduled: AtomicU64,
+    // Index of the running task plus one, 0 if there is none
+    running: AtomicU32,
+    // Monotonic time in nanoseconds when the running task started, 0 if there is none
+    task_started_at: AtomicU64,
+    alive: AtomicBool,
+    // Linux thread id of the dispatcher
+    tid: AtomicU32,
+}
+
+impl Progress {
+    pub const fn new() -> Self {
+        Self {
+            started: AtomicU64::new(0),
+            finished: AtomicU64::new(0),
+            released: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            scheduled: AtomicU64::new(0),
+            received_scheduled: AtomicU64::new(0),
+            running: AtomicU32::new(0),
+            task_started_at: AtomicU64::new(0),
+            alive: AtomicBool::new(false),
+            tid: AtomicU32::new(0),
+        }
+    }
+
+    /// Called when a task is spawned for immediate execution
+    #[inline(always)]
+    pub fn release(&self) {
+        self.released.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called when a task is spawned for execution at a later instant
+    #[inline(always)]
+    pub fn schedule(&self) {
+        self.scheduled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called by the dispatcher before a task runs. `released` is `false` for scheduled tasks.
+    #[inline(always)]
+    pub fn start(&self, released: bool, task: u32) {
+        if released {
+            self.received.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.received_scheduled.fetch_add(1, Ordering::Relaxed);
+        }
+        self.running.store(task + 1, Ordering::Relaxed);
+        self.task_started_at
+            .store(monotonic_nanos(), Ordering::Relaxed);
+        self.started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called by the dispatcher after a task returns
+    #[inline(always)]
+    pub fn finish(&self) {
+        self.running.store(0, Ordering::Relaxed);
+        self.task_started_at.store(0, Ordering::Relaxed);
+        self.finished.fetch_add(1, Ordering::Release);
+    }
+
+    /// Marks the dispatcher thread alive until the returned guard is dropped, i.e. by a panic
+    pub fn alive(&'static self) -> AliveGuard {
+        self.tid
+            .store(unsafe { libc::gettid() } as u32, Ordering::Relaxed);
+        self.alive.store(true, Ordering::Relaxed);
+        AliveGuard(self)
+    }
+
+    /// Returns the Linux thread id of the dispatcher, 0 before it started
+    pub fn tid(&self) -> u32 {
+        self.tid.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of tasks started by the dispatcher
+    pub fn dispatched(&self) -> u64 {
+        self.started.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let finished = self.finished.load(Ordering::Acquire);
+        let received = self.received.load(Ordering::Relaxed);
+        ProgressSnapshot {
+            finished,
+            started: self.started.load(Ordering::Relaxed),
+            pending: self
+                .released
+                .load(Ordering::Relaxed)
+                .saturating_sub(received),
+            scheduled: self
+                .scheduled
+                .load(Ordering::Relaxed)
+                .saturating_sub(self.received_scheduled.load(Ordering::Relaxed)),
+            running: self.running.load(Ordering::Relaxed).checked_sub(1),
+            task_started_at: self.task_started_at.load(Ordering::Relaxed),
+            alive: self.alive.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Clears the alive flag of [`Progress`] when dropped
+pub struct AliveGuard(&'static Progress);
+
+impl Drop for AliveGuard {
+    fn drop(&mut self) {
+        self.0.alive.store(false, Ordering::Relaxed);
+    }
+}
+
+/// State of [`Progress`] at some point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressSnapshot {
+    started: u64,
+    finished: u64,
+    pending: u64,
+    scheduled: u64,
+    running: Option<u32>,
+    task_started_at: u64,
+    alive: bool,
+}
+
+impl ProgressSnapshot {
+    /// Checks that the dispatcher thread is alive and either started a new task since `prev` or
+    /// is waiting for tasks with none pending
+    ///
+    /// A dispatcher that runs the same task since `prev`, or does not receive released tasks, is
+    /// considered stalled.
+    pub fn is_live(&self, prev: &ProgressSnapshot) -> bool {
+        let idle = self.started == self.finished && self.pending == 0;
+        self.alive && (self.started != prev.started || idle)
+    }
+
+    /// Returns the number of tasks started so far
+    pub fn started(&self) -> u64 {
+        self.started
+    }
+
+    /// Returns the number of released tasks that the dispatcher has not received yet
+    pub fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    /// Returns the number of scheduled tasks that the dispatcher has not received yet
+    pub fn scheduled(&self) -> u64 {
+        self.scheduled
+    }
+
+    /// Returns the index of the running task in the app, if any
+    pub fn running(&self) -> Option<u32> {
+        self.running
+    }
+
+    /// Returns how long the running task has been running at `now`, see [`monotonic_nanos`]
+    pub fn running_for(&self, now: u64) -> Option<Duration> {
+        match self.task_started_at {
+            0 => None,
+            started_at => Some(Duration::from_nanos(now.saturating_sub(started_at))),
+        }
+    }
+
+    /// Returns `false` if the dispatcher thread has exited
+    pub fn is_alive(&self) -> bool {
+        self.alive
+    }
+}
+
+/// Returns `CLOCK_MONOTONIC` time in nanoseconds
+pub fn monotonic_nanos() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Handle to a running application
+pub struct AppHandle {
+    threads: Vec<JoinHandle<()>>,
+    idle: &'static IdleStop,
+    shutdown: fn(),
+    stats: fn() -> Stats,
+    introspect: fn() -> Snapshot,
+}
+
+impl AppHandle {
+    #[doc(hidden)]
+    pub fn new(
+        threads: Vec<JoinHandle<()>>,
+        idle: &'static IdleStop,
+        shutdown: fn(),
+        stats: fn() -> Stats,
+        introspect: fn() -> Snapshot,
+    ) -> Self {
+        Self {
+            threads,
+            idle,
+            shutdown,
+            stats,
+            introspect,
+        }
+    }
+
+    /// Requests the dispatcher threads to stop
+    ///
+    /// Tasks that are already in the run queues are run first, scheduled tasks are discarded.
+    pub fn request_shutdown(&self) {
+        (self.shutdown)();
+    }
+
+    /// Returns the current statistics
+    pub fn stats(&self) -> Stats {
+        (self.stats)()
+    }
+
+    /// Returns the current state of run queues and tasks, same as `app::introspect`
+    pub fn introspect(&self) -> Snapshot {
+        (self.introspect)()
+    }
+
+    /// Waits for all dispatcher threads to stop
+    ///
+    /// Returns the panic payload if any of the threads panicked. `#[idle]` never returns. If it
+    /// checks `cx.shutdown_requested()`, `join` waits until it calls `cx.shutdown_done()`, at most
+    /// [`IDLE_STOP_TIMEOUT`], before the logging, tracing and metrics threads are stopped. Idle
+    /// keeps running after `join` returns and tasks it spawns afterwards are never dispatched.
+    pub fn join(self) -> thread::Result<()> {
+        let mut result = Ok(());
+        for thread in self.threads {
+            if let Err(err) = thread.join() {
+                result = result.and(Err(err));
+            }
+        }
+        if !self.idle.wait(IDLE_STOP_TIMEOUT) {
+            eprintln!(
+                "rtic: idle did not call `shutdown_done` within {:?}",
+                IDLE_STOP_TIMEOUT
+            );
+        }
+        crate::metrics::stop();
+        crate::shm::stop();
+        crate::trace::stop();
+        crate::profiling::flush();
+        crate::log::stop();
+        result
+    }
+}
+
+/// Stops the application and exits the process on Ctrl-C, SIGTERM or SIGHUP
+///
+/// Task statistics are printed before exiting, once `#[idle]` has finished its cleanup (see
+/// [`AppHandle::join`]).
+///
+/// Used by `app::run`, in library mode signals are left to the user.
+#[doc(hidden)]
+pub fn exit_on_signal(handle: AppHandle) {
+    let mut handle = Some(handle);
+    let result = ctrlc::set_handler(move || {
+        // Fixes newline in terminal
+        println!();
+
+        let code = match handle.take() {
+            Some(handle) => {
+                handle.request_shutdown();
+                let stats = handle.stats();
+                let result = handle.join();
+                crate::stats::print(&stats.tasks);
+
+                match result {
+                    Ok(()) => 0,
+                    Err(_) => 1,
+                }
+            }
+            // Already stopping
+            None => return,
+        };
+
+        std::process::exit(code);
+    });
+
+    if let Err(err) = result {
+        eprintln!("rtic: failed to set signal handler: {}", err);
+    }
+}
+
+/// Spawns a named thread with an optional stack size
+///
+/// The error names the thread.
+#[doc(hidden)]
+pub fn spawn_thread<F, T>(name: &str, stack_size: Option<usize>, f: F) -> io::Result<JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let mut builder = thread::Builder::new().name(name.to_string());
+    if let Some(size) = stack_size {
+        builder = builder.stack_size(size);
+    }
+
+    builder.spawn(f).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to spawn thread {}: {}", name, err),
+        )
+    })
+}
+
+/// Name, stack size and function of a dispatcher thread
+#[doc(hidden)]
+pub type DispatcherThread = (&'static str, Option<usize>, fn());
+
+/// Spawns all dispatcher threads, or none of them
+///
+/// If a thread fails to spawn, `barrier` is aborted and the threads spawned so far are joined.
+#[doc(hidden)]
+pub fn spawn_dispatchers(
+    barrier: &InitBarrier,
+    dispatchers: Vec<DispatcherThread>,
+) -> io::Result<Vec<JoinHandle<()>>> {
+    let mut threads = Vec::with_capacity(dispatchers.len());
+    for (name, stack_size, f) in dispatchers {
+        match spawn_thread(name, stack_size, f) {
+            Ok(thread) => threads.push(thread),
+            Err(err) => {
+                abort_startup(barrier, threads);
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(threads)
+}
+
+/// Releases the dispatcher threads waiting at `barrier` without running any task and joins them
+#[doc(hidden)]
+pub fn abort_startup(barrier: &InitBarrier, threads: Vec<JoinHandle<()>>) {
+    barrier.abort();
+    for thread in threads {
+        thread.join().ok();
+    }
+}
+
+/// Barrier at which the threads of an application wait until all of them are initialized
+///
+/// Unlike [`std::sync::Barrier`] it can be aborted if the startup fails after some of the threads
+/// were spawned.
+#[doc(hidden)]
+pub struct InitBarrier {
+    count: usize,
+    // Number of waiting threads and whether the barrier was aborted
+    state: Mutex<(usize, bool)>,
+    condvar: Condvar,
+}
+
+impl InitBarrier {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count,
+            state: Mutex::new((0, false)),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Waits until `count` threads wait. Returns `false` if the barrier was aborted instead.
+    pub fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        if state.0 >= self.count {
+            self.condvar.notify_all();
+        }
+
+        let state = self
+            .condvar
+            .wait_while(state, |&mut (waiting, aborted)| {
+                waiting < self.count && !aborted
+            })
+            .unwrap();
+        state.0 >= self.count
+    }
+
+    /// Releases all waiting and future threads, whose [`wait`](Self::wait) returns `false`
+    pub fn abort(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.condvar.notify_all();
+    }
+}
+
+/// How long [`AppHandle::join`] waits for `#[idle]` to finish its cleanup
+pub const IDLE_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Acknowledgement of a shutdown request by `#[idle]`
+///
+/// Idle is only waited for once it has checked `cx.shutdown_requested()`, an idle that never
+/// checks it would never acknowledge the request.
+#[doc(hidden)]
+pub struct IdleStop {
+    watching: AtomicBool,
+    done: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl IdleStop {
+    pub const fn new() -> Self {
+        Self {
+            watching: AtomicBool::new(false),
+            done: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Marks that idle checks for shutdown requests
+    pub fn watch(&self) {
+        self.watching.store(true, Ordering::Relaxed);
+    }
+
+    /// Acknowledges the shutdown request
+    pub fn done(&self) {
+        *self.done.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+
+    /// Waits until idle acknowledges the shutdown request, if it checks for one. Returns `false`
+    /// on timeout.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        if !self.watching.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let (_done, result) = self
+            .condvar
+            .wait_timeout_while(self.done.lock().unwrap(), timeout, |done| !*done)
+            .unwrap();
+        !result.timed_out()
+    }
+}
+
+impl Default for IdleStop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
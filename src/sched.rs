@@ -0,0 +1,165 @@
+//! Scheduling of RTIC threads
+//!
+//! Whether threads use `SCHED_FIFO` is decided at startup, so that the same binary can run on the
+//! target hardware and on a development machine. The mode is selected by (in order of precedence):
+//!
+//! - [`Config::mode`](crate::Config::mode) when the app is started with `app::start`
+//! - `--rtic-realtime` or `--rtic-no-realtime` command line flag
+//! - `RTIC_REALTIME` environment variable: `1`, `0` or `auto`
+//! - `auto` if the `rt` feature is enabled (default), otherwise non real-time
+//!
+//! In `auto` mode real-time scheduling is used if the process is permitted to.
+//!
+//! The command line flags are not removed from `std::env::args()`, so an application that parses
+//! its own arguments has to accept them or parse [`args`] instead, which skips them.
+//!
+//! In non real-time mode priorities are mapped to `nice` values, so that the relative ordering of
+//! threads is still approximated.
+
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Environment variable that selects the scheduling mode
+pub const ENV_VAR: &str = "RTIC_REALTIME";
+/// Command line flag that forces real-time scheduling
+pub const FLAG_REALTIME: &str = "--rtic-realtime";
+/// Command line flag that disables real-time scheduling
+pub const FLAG_NO_REALTIME: &str = "--rtic-no-realtime";
+
+// Highest `nice` value (lowest priority)
+const MAX_NICE: u8 = 19;
+
+static REALTIME: AtomicBool = AtomicBool::new(false);
+// Highest priority used by the application, mapped to nice 0
+static MAX_PRIORITY: AtomicU8 = AtomicU8::new(0);
+
+/// Scheduling mode of RTIC threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// `SCHED_FIFO` with task priorities
+    RealTime,
+    /// `SCHED_OTHER` with priorities mapped to `nice` values
+    Normal,
+}
+
+/// Selects the scheduling mode from command line, environment and permissions
+///
+/// `max_priority` is the highest priority used by the application. `mode` overrides all other
+/// sources if set.
+pub fn init(max_priority: u8, mode: Option<Mode>) -> Mode {
+    MAX_PRIORITY.store(max_priority, Ordering::Relaxed);
+
+    if let Some(mode) = mode {
+        set_mode(mode);
+        return mode;
+    }
+
+    let requested = std::env::args()
+        .find_map(|arg| match &*arg {
+            FLAG_REALTIME => Some("1".to_string()),
+            FLAG_NO_REALTIME => Some("0".to_string()),
+            _ => None,
+        })
+        .or_else(|| std::env::var(ENV_VAR).ok());
+
+    let mode = match requested.as_deref() {
+        Some("1") | Some("on") | Some("true") => Mode::RealTime,
+        Some("0") | Some("off") | Some("false") => Mode::Normal,
+        Some("auto") => auto_mode(max_priority),
+        Some(other) => {
+            eprintln!("rtic: ignoring invalid {} value `{}`", ENV_VAR, other);
+            default_mode(max_priority)
+        }
+        None => default_mode(max_priority),
+    };
+
+    set_mode(mode);
+    mode
+}
+
+/// Returns the command line arguments without the flags that select the scheduling mode
+///
+/// ```
+/// let args: Vec<String> = rtic::sched::args().skip(1).collect();
+/// ```
+pub fn args() -> impl Iterator<Item = String> {
+    std::env::args().filter(|arg| arg != FLAG_REALTIME && arg != FLAG_NO_REALTIME)
+}
+
+fn default_mode(max_priority: u8) -> Mode {
+    if cfg!(feature = "rt") {
+        auto_mode(max_priority)
+    } else {
+        Mode::Normal
+    }
+}
+
+fn auto_mode(max_priority: u8) -> Mode {
+    if crate::environment::can_set_priority(max_priority) {
+        Mode::RealTime
+    } else {
+        eprintln!("rtic: real-time scheduling is not permitted, using normal scheduling");
+        Mode::Normal
+    }
+}
+
+/// Returns the current scheduling mode
+pub fn mode() -> Mode {
+    if REALTIME.load(Ordering::Relaxed) {
+        Mode::RealTime
+    } else {
+        Mode::Normal
+    }
+}
+
+/// Returns the highest priority used by the application
+pub fn max_priority() -> u8 {
+    MAX_PRIORITY.load(Ordering::Relaxed)
+}
+
+/// Sets the scheduling mode for threads initialized afterwards
+pub fn set_mode(mode: Mode) {
+    REALTIME.store(mode == Mode::RealTime, Ordering::Relaxed);
+}
+
+/// Sets scheduling policy and priority of the current thread
+///
+/// In real-time mode priority 0 corresponds to the default `SCHED_OTHER` policy and others to
+/// `SCHED_FIFO`.
+pub fn init_thread(priority: pcp_mutex::Priority) {
+    match mode() {
+        Mode::RealTime if priority > 0 => {
+            pcp_mutex::thread::init_fifo_priority(priority).expect("Error setting thread priority");
+        }
+        Mode::RealTime => {
+            set_normal_policy(0).expect("Error setting thread priority");
+            pcp_mutex::thread::update_priority().expect("Error setting thread priority");
+        }
+        Mode::Normal => {
+            let nice = MAX_PRIORITY
+                .load(Ordering::Relaxed)
+                .saturating_sub(priority)
+                .min(MAX_NICE);
+            // Best effort, lowering nice value requires the same permissions as real-time
+            set_normal_policy(nice).ok();
+        }
+    }
+}
+
+/// Sets `SCHED_OTHER` policy with a given `nice` value for the current thread
+fn set_normal_policy(nice: u8) -> std::io::Result<()> {
+    let param = libc::sched_param { sched_priority: 0 };
+
+    unsafe {
+        if libc::sched_setscheduler(0, libc::SCHED_OTHER, &param) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // `nice` is a per-thread attribute on Linux
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        if libc::setpriority(libc::PRIO_PROCESS, tid, nice as libc::c_int) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
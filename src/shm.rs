@@ -0,0 +1,724 @@
+//! Statistics region in shared memory, read by `rtic-top`
+//!
+//! With the `shm` feature a low priority thread periodically writes the [`Stats`] and
+//! [`Snapshot`] of the application into the POSIX shared memory object `/rtic-<pid>`, i.e.
+//! `/dev/shm/rtic-<pid>`. Other processes map it with [`read`] without attaching a debugger or
+//! opening a socket. The object is removed when the application stops.
+//!
+//! The size of the region is fixed once it is created, names are truncated to a fixed length and
+//! the numbers of levels, tasks and locks do not change. Writes are guarded by a sequence counter
+//! in the header, which is odd while a write is in progress. Readers retry until they get a
+//! consistent copy. Timing percentiles are only available with the `stats` feature.
+
+use std::{
+    ffi::CString,
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64, AtomicU8, Ordering},
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{
+    introspect::Snapshot,
+    runtime::{monotonic_nanos, Stats},
+    stats::Histogram,
+};
+
+/// Whether the region is written
+pub const ENABLED: bool = cfg!(feature = "shm");
+
+/// Identifies the file and the layout version
+const MAGIC: &[u8; 8] = b"RTICSHM1";
+
+/// Offset of the sequence counter
+const SEQ_OFFSET: usize = 8;
+
+/// Size of the magic number and the sequence counter
+const HEADER_LEN: usize = SEQ_OFFSET + 8;
+
+/// Interval at which the region is written
+const PERIOD: Duration = Duration::from_millis(100);
+
+/// Length of fixed-size names, longer names are truncated
+const NAME_LEN: usize = 32;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static WRITER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+/// Returns the path of the region of a process
+pub fn path(pid: u32) -> PathBuf {
+    format!("/dev/shm/rtic-{}", pid).into()
+}
+
+/// Returns the name of the shared memory object of a process
+fn name(pid: u32) -> CString {
+    CString::new(format!("/rtic-{}", pid)).unwrap()
+}
+
+/// Contents of the region
+#[derive(Debug, Clone)]
+pub struct Region {
+    /// Process that wrote the region
+    pub pid: u32,
+    /// `CLOCK_MONOTONIC` time of the write
+    pub timestamp: Duration,
+    /// Whether timing is collected, i.e. the `stats` feature is enabled
+    pub stats: bool,
+    /// Priority levels, ordered by priority
+    pub levels: Vec<LevelRecord>,
+    /// Software tasks
+    pub tasks: Vec<TaskRecord>,
+    /// Shared resources that are locked, empty without the `stats` feature
+    pub locks: Vec<LockRecord>,
+}
+
+/// Priority level, see [`crate::introspect::Level`]
+#[derive(Debug, Clone)]
+pub struct LevelRecord {
+    pub priority: u8,
+    pub thread: String,
+    pub tid: u32,
+    pub alive: bool,
+    pub queued: u64,
+    pub scheduled: u64,
+    pub dispatched: u64,
+    /// Name of the running task and how long it has been running
+    pub running: Option<(String, Duration)>,
+}
+
+/// Software task, see [`crate::introspect::Task`] and [`crate::stats::TaskStats`]
+#[derive(Debug, Clone)]
+pub struct TaskRecord {
+    pub name: String,
+    pub priority: u8,
+    /// Number of completed runs
+    pub count: u64,
+    pub latency: Percentiles,
+    pub wall_time: Percentiles,
+    pub cpu_time: Percentiles,
+    pub used: u64,
+    pub capacity: u64,
+    pub high_water: u64,
+    pub spawn_failures: u64,
+    /// Runs that completed after the deadline
+    pub deadline_misses: u64,
+}
+
+/// Shared resource, see [`crate::stats::LockStats`]
+#[derive(Debug, Clone)]
+pub struct LockRecord {
+    pub name: String,
+    /// Number of locks
+    pub count: u64,
+    /// Time until the resource is acquired
+    pub wait: Percentiles,
+    /// Time the resource is held
+    pub held: Percentiles,
+}
+
+/// Summary of a [`Histogram`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl From<&Histogram> for Percentiles {
+    fn from(histogram: &Histogram) -> Self {
+        Self {
+            p50: histogram.percentile(0.5),
+            p99: histogram.percentile(0.99),
+            max: histogram.max(),
+        }
+    }
+}
+
+/// Starts the thread that writes the region
+///
+/// Called by the generated code once all dispatcher threads are spawned.
+#[doc(hidden)]
+pub fn publish(name: &str, stats: fn() -> Stats, introspect: fn() -> Snapshot) {
+    if !ENABLED {
+        return;
+    }
+
+    let pid = std::process::id();
+    let len = encode(&stats(), &introspect()).len();
+    let region = match Mapping::create(pid, len) {
+        Ok(region) => region,
+        Err(err) => {
+            eprintln!(
+                "rtic: shm: failed to create {}: {}",
+                path(pid).display(),
+                err
+            );
+            return;
+        }
+    };
+
+    let result = crate::runtime::spawn_thread(name, None, move || {
+        // Below all tasks, writing must not delay them
+        crate::sched::init_thread(0);
+
+        while !STOP.load(Ordering::Relaxed) {
+            region.write(&encode(&stats(), &introspect()));
+            std::thread::sleep(PERIOD);
+        }
+
+        unlink(pid);
+    });
+    match result {
+        Ok(handle) => *WRITER.lock().unwrap() = Some(handle),
+        Err(err) => {
+            unlink(pid);
+            eprintln!("rtic: shm: {}", err);
+        }
+    }
+}
+
+/// Stops writing and removes the region
+///
+/// Called when the application stops.
+#[doc(hidden)]
+pub fn stop() {
+    if let Some(handle) = WRITER.lock().unwrap().take() {
+        STOP.store(true, Ordering::Relaxed);
+        handle.join().ok();
+    }
+}
+
+/// Reads the region of a process
+pub fn read(pid: u32) -> io::Result<Region> {
+    let region = Mapping::open(pid)?;
+    for _ in 0..100 {
+        if let Some(bytes) = region.read() {
+            let mut decoder = Decoder { bytes: &bytes };
+            decoder
+                .header()
+                .ok_or_else(|| invalid_data("not an rtic statistics region"))?;
+            return decoder
+                .region()
+                .ok_or_else(|| invalid_data("malformed region"));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::WouldBlock,
+        "region is being written",
+    ))
+}
+
+fn unlink(pid: u32) {
+    unsafe { libc::shm_unlink(name(pid).as_ptr()) };
+}
+
+/// Shared memory object mapped into the process
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// Only accessed through atomics
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    /// Creates the region of a process, replacing a stale one of an earlier process with the same
+    /// pid
+    fn create(pid: u32, len: usize) -> io::Result<Self> {
+        let name = name(pid);
+        unsafe {
+            let fd = libc::shm_open(
+                name.as_ptr(),
+                libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC,
+                0o644,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let result = if libc::ftruncate(fd, len as libc::off_t) < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Self::map(fd, len, libc::PROT_READ | libc::PROT_WRITE)
+            };
+            libc::close(fd);
+            if result.is_err() {
+                libc::shm_unlink(name.as_ptr());
+            }
+            result
+        }
+    }
+
+    /// Maps the region of a process read-only
+    fn open(pid: u32) -> io::Result<Self> {
+        unsafe {
+            let fd = libc::shm_open(name(pid).as_ptr(), libc::O_RDONLY, 0);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut stat: libc::stat = std::mem::zeroed();
+            let result = if libc::fstat(fd, &mut stat) < 0 {
+                Err(io::Error::last_os_error())
+            } else if (stat.st_size as usize) < HEADER_LEN {
+                Err(invalid_data("not an rtic statistics region"))
+            } else {
+                Self::map(fd, stat.st_size as usize, libc::PROT_READ)
+            };
+            libc::close(fd);
+            result
+        }
+    }
+
+    unsafe fn map(fd: libc::c_int, len: usize, prot: libc::c_int) -> io::Result<Self> {
+        let ptr = libc::mmap(std::ptr::null_mut(), len, prot, libc::MAP_SHARED, fd, 0);
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn seq(&self) -> &AtomicU64 {
+        // Mappings are page aligned
+        unsafe { &*(self.ptr.add(SEQ_OFFSET) as *const AtomicU64) }
+    }
+
+    /// Bytes of the region except the sequence counter with their offsets, the other process may
+    /// change them at any time
+    fn bytes(&self) -> impl Iterator<Item = (usize, &AtomicU8)> {
+        let bytes = unsafe { std::slice::from_raw_parts(self.ptr as *const AtomicU8, self.len) };
+        bytes
+            .iter()
+            .enumerate()
+            .filter(|(offset, _)| !(SEQ_OFFSET..HEADER_LEN).contains(offset))
+    }
+
+    /// Writes an encoded region, its sequence counter is ignored
+    fn write(&self, bytes: &[u8]) {
+        let seq = self.seq().load(Ordering::Relaxed);
+        // Odd while the contents are inconsistent
+        self.seq().store(seq + 1, Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+
+        for (offset, byte) in self.bytes() {
+            byte.store(bytes.get(offset).copied().unwrap_or(0), Ordering::Relaxed);
+        }
+
+        self.seq().store(seq + 2, Ordering::Release);
+    }
+
+    /// Copies the region, returns `None` if it was not written yet or a write was in progress
+    fn read(&self) -> Option<Vec<u8>> {
+        let seq = self.seq().load(Ordering::Acquire);
+        if seq == 0 || seq % 2 == 1 {
+            return None;
+        }
+
+        let mut bytes = vec![0; self.len];
+        bytes[SEQ_OFFSET..HEADER_LEN].copy_from_slice(&seq.to_le_bytes());
+        for (offset, byte) in self.bytes() {
+            bytes[offset] = byte.load(Ordering::Relaxed);
+        }
+
+        // Retry if another write happened meanwhile
+        atomic::fence(Ordering::Acquire);
+        (self.seq().load(Ordering::Relaxed) == seq).then_some(bytes)
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn encode(stats: &Stats, snapshot: &Snapshot) -> Vec<u8> {
+    let mut e = Encoder::default();
+    e.bytes.extend_from_slice(MAGIC);
+    e.u64(0);
+    e.u32(std::process::id());
+    e.u64(monotonic_nanos());
+    e.u8(crate::stats::ENABLED as u8);
+    e.u32(snapshot.levels.len() as u32);
+    e.u32(snapshot.tasks.len() as u32);
+    e.u32(stats.locks.len() as u32);
+
+    for level in &snapshot.levels {
+        e.u8(level.priority);
+        e.name(level.thread);
+        e.u32(level.tid);
+        e.u8(level.alive as u8);
+        e.u64(level.queued);
+        e.u64(level.scheduled);
+        e.u64(level.dispatched);
+        e.u8(level.running.is_some() as u8);
+        let (task, time) = level.running.unwrap_or(("", Duration::ZERO));
+        e.name(task);
+        e.duration(time);
+    }
+
+    for task in &snapshot.tasks {
+        e.name(task.name);
+        e.u8(task.priority);
+        match stats.tasks.iter().find(|stats| stats.name == task.name) {
+            Some(stats) => {
+                e.u64(stats.wall_time.count());
+                e.percentiles(&stats.latency);
+                e.percentiles(&stats.wall_time);
+                e.percentiles(&stats.cpu_time);
+            }
+            None => {
+                e.u64(0);
+                for _ in 0..3 {
+                    e.percentiles(&Histogram::new());
+                }
+            }
+        }
+        e.u64(task.used as u64);
+        e.u64(task.capacity as u64);
+        e.u64(task.high_water as u64);
+        e.u64(task.spawn_failures as u64);
+        let misses = stats
+            .deadlines
+            .iter()
+            .find(|deadline| deadline.name == task.name)
+            .map_or(0, |deadline| deadline.missed());
+        e.u64(misses);
+    }
+
+    for lock in &stats.locks {
+        e.name(lock.name);
+        e.u64(lock.held.count());
+        e.percentiles(&lock.wait);
+        e.percentiles(&lock.held);
+    }
+
+    e.bytes
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn duration(&mut self, value: Duration) {
+        self.u64(value.as_nanos().min(u64::MAX as u128) as u64);
+    }
+
+    fn name(&mut self, name: &str) {
+        let mut bytes = [0; NAME_LEN];
+        let len = name.len().min(NAME_LEN);
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn percentiles(&mut self, histogram: &Histogram) {
+        let percentiles = Percentiles::from(histogram);
+        self.duration(percentiles.p50);
+        self.duration(percentiles.p99);
+        self.duration(percentiles.max);
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.bytes.len() < N {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(N);
+        self.bytes = tail;
+        head.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[value]| value)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn duration(&mut self) -> Option<Duration> {
+        self.u64().map(Duration::from_nanos)
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let bytes = self.take::<NAME_LEN>()?;
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+        Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+
+    fn percentiles(&mut self) -> Option<Percentiles> {
+        Some(Percentiles {
+            p50: self.duration()?,
+            p99: self.duration()?,
+            max: self.duration()?,
+        })
+    }
+
+    /// Checks the magic number and returns the sequence counter
+    fn header(&mut self) -> Option<u64> {
+        if &self.take::<8>()? != MAGIC {
+            return None;
+        }
+        self.u64()
+    }
+
+    fn region(&mut self) -> Option<Region> {
+        let pid = self.u32()?;
+        let timestamp = self.duration()?;
+        let stats = self.u8()? != 0;
+        let levels = self.u32()?;
+        let tasks = self.u32()?;
+        let locks = self.u32()?;
+
+        Some(Region {
+            pid,
+            timestamp,
+            stats,
+            levels: (0..levels).map(|_| self.level()).collect::<Option<_>>()?,
+            tasks: (0..tasks).map(|_| self.task()).collect::<Option<_>>()?,
+            locks: (0..locks).map(|_| self.lock()).collect::<Option<_>>()?,
+        })
+    }
+
+    fn level(&mut self) -> Option<LevelRecord> {
+        let priority = self.u8()?;
+        let thread = self.name()?;
+        let tid = self.u32()?;
+        let alive = self.u8()? != 0;
+        let queued = self.u64()?;
+        let scheduled = self.u64()?;
+        let dispatched = self.u64()?;
+        let running = self.u8()? != 0;
+        let task = self.name()?;
+        let time = self.duration()?;
+
+        Some(LevelRecord {
+            priority,
+            thread,
+            tid,
+            alive,
+            queued,
+            scheduled,
+            dispatched,
+            running: running.then_some((task, time)),
+        })
+    }
+
+    fn task(&mut self) -> Option<TaskRecord> {
+        Some(TaskRecord {
+            name: self.name()?,
+            priority: self.u8()?,
+            count: self.u64()?,
+            latency: self.percentiles()?,
+            wall_time: self.percentiles()?,
+            cpu_time: self.percentiles()?,
+            used: self.u64()?,
+            capacity: self.u64()?,
+            high_water: self.u64()?,
+            spawn_failures: self.u64()?,
+            deadline_misses: self.u64()?,
+        })
+    }
+
+    fn lock(&mut self) -> Option<LockRecord> {
+        Some(LockRecord {
+            name: self.name()?,
+            count: self.u64()?,
+            wait: self.percentiles()?,
+            held: self.percentiles()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        deadline::TaskDeadline,
+        introspect::{Level, Task},
+        stats::{LockStats, TaskStats},
+    };
+    use std::time::Instant;
+
+    fn sample() -> (Stats, Snapshot) {
+        let task: &'static TaskStats = Box::leak(Box::new(TaskStats::new("tick", 2)));
+        task.latency.record(Duration::from_micros(3));
+        task.wall_time.record(Duration::from_micros(40));
+        task.cpu_time.record(Duration::from_micros(30));
+        let deadline: &'static TaskDeadline = Box::leak(Box::new(TaskDeadline::new(
+            "tick",
+            2,
+            Duration::from_millis(1),
+            false,
+        )));
+        deadline.finish(Some(Instant::now() - Duration::from_secs(1)), |_| {});
+        let lock: &'static LockStats = Box::leak(Box::new(LockStats::new("counter")));
+        lock.held.record(Duration::from_micros(5));
+
+        let stats = Stats {
+            threads: vec![],
+            tasks: vec![task],
+            deadlines: vec![deadline],
+            locks: vec![lock],
+        };
+        let snapshot = Snapshot {
+            levels: vec![Level {
+                priority: 2,
+                thread: "app-P2",
+                tid: 42,
+                queued: 1,
+                scheduled: 2,
+                running: Some((
+                    "a_task_name_longer_than_the_fixed_length",
+                    Duration::from_millis(7),
+                )),
+                dispatched: 3,
+                alive: true,
+            }],
+            tasks: vec![
+                Task {
+                    name: "tick",
+                    priority: 2,
+                    used: 1,
+                    capacity: 4,
+                    high_water: 3,
+                    spawn_failures: 5,
+                },
+                Task {
+                    name: "idle_task",
+                    priority: 1,
+                    used: 0,
+                    capacity: 1,
+                    high_water: 0,
+                    spawn_failures: 0,
+                },
+            ],
+        };
+        (stats, snapshot)
+    }
+
+    #[test]
+    fn encode_decode() {
+        let (stats, snapshot) = sample();
+        let bytes = encode(&stats, &snapshot);
+        let mut decoder = Decoder { bytes: &bytes };
+        assert_eq!(decoder.header(), Some(0));
+        let region = decoder.region().unwrap();
+        assert!(decoder.bytes.is_empty());
+
+        assert_eq!(region.pid, std::process::id());
+        assert_eq!(region.stats, crate::stats::ENABLED);
+
+        let level = &region.levels[0];
+        assert_eq!(
+            (
+                level.priority,
+                level.thread.as_str(),
+                level.tid,
+                level.alive
+            ),
+            (2, "app-P2", 42, true)
+        );
+        assert_eq!((level.queued, level.scheduled, level.dispatched), (1, 2, 3));
+        // Names are truncated
+        let (running, time) = level.running.clone().unwrap();
+        assert_eq!(running, "a_task_name_longer_than_the_fixe");
+        assert_eq!(time, Duration::from_millis(7));
+
+        let tick = &region.tasks[0];
+        assert_eq!(
+            (tick.name.as_str(), tick.priority, tick.count),
+            ("tick", 2, 1)
+        );
+        assert_eq!(tick.latency.max, Duration::from_micros(3));
+        assert_eq!(tick.wall_time.max, Duration::from_micros(40));
+        assert_eq!(tick.cpu_time.max, Duration::from_micros(30));
+        assert_eq!(
+            (
+                tick.used,
+                tick.capacity,
+                tick.high_water,
+                tick.spawn_failures
+            ),
+            (1, 4, 3, 5)
+        );
+        assert_eq!(tick.deadline_misses, 1);
+
+        // Tasks without statistics have empty percentiles
+        let idle = &region.tasks[1];
+        assert_eq!((idle.name.as_str(), idle.count), ("idle_task", 0));
+        assert_eq!(idle.wall_time.max, Duration::ZERO);
+        assert_eq!(idle.deadline_misses, 0);
+
+        let lock = &region.locks[0];
+        assert_eq!((lock.name.as_str(), lock.count), ("counter", 1));
+        assert_eq!(lock.held.max, Duration::from_micros(5));
+
+        // Truncated regions are rejected
+        let mut decoder = Decoder {
+            bytes: &bytes[..bytes.len() - 1],
+        };
+        decoder.header().unwrap();
+        assert!(decoder.region().is_none());
+    }
+
+    #[test]
+    fn shared_region() {
+        let (stats, mut snapshot) = sample();
+        // No application runs in the test process
+        let pid = std::process::id();
+        let bytes = encode(&stats, &snapshot);
+        let region = Mapping::create(pid, bytes.len()).unwrap();
+
+        // Not written yet
+        assert!(region.read().is_none());
+        region.write(&bytes);
+        assert_eq!(read(pid).unwrap().levels[0].dispatched, 3);
+
+        snapshot.levels[0].dispatched = 4;
+        region.write(&encode(&stats, &snapshot));
+        assert_eq!(read(pid).unwrap().levels[0].dispatched, 4);
+
+        // Write in progress
+        region.seq().fetch_add(1, Ordering::Relaxed);
+        assert!(region.read().is_none());
+        assert_eq!(read(pid).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        unlink(pid);
+        assert!(read(pid).is_err());
+    }
+}
@@ -1,15 +1,48 @@
 // Preallocated storage similar to slab crate, but fixed size
 
-use std::{
+use std::mem::{self, MaybeUninit};
+
+// Synchronization primitives are swapped for model checking, see `tests/slab.rs`
+#[cfg(not(loom))]
+use self::cell::UnsafeCell;
+#[cfg(loom)]
+use loom::{
     cell::UnsafeCell,
-    mem::MaybeUninit,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
 };
+#[cfg(not(loom))]
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+// `std::cell::UnsafeCell` with the API of `loom::cell::UnsafeCell`
+#[cfg(not(loom))]
+mod cell {
+    pub(super) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+    impl<T> UnsafeCell<T> {
+        pub(super) fn new(data: T) -> Self {
+            Self(std::cell::UnsafeCell::new(data))
+        }
+
+        pub(super) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+            f(self.0.get())
+        }
+
+        pub(super) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+            f(self.0.get())
+        }
+    }
+}
 
 /// Handle to a queued item
+///
+/// Handle must be passed to [`SlabReceiver::remove`], otherwise the slot is leaked. Leaked handles
+/// are reported in debug builds.
 #[derive(Debug)]
 pub struct SlabHandle {
     index: usize,
@@ -17,17 +50,36 @@ pub struct SlabHandle {
     ptr: usize,
 }
 
-// N should be a power of 2 so that overflows work correctly, but 64bit usize is unlikely to ever overflow
+impl SlabHandle {
+    /// Returns index of the slot, which is unique among queued items of the slab
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+// Marks the end of the free list
+const NONE: u64 = u32::MAX as u64;
+// Lower half of `free_head` is the index of the first free slot, upper half is a tag
+const INDEX_MASK: u64 = NONE;
+const TAG: u64 = 1 << 32;
+
+// Free slots form a lock-free stack, so that slots can be freed from any thread and a preempted
+// thread never blocks the others. N must be smaller than u32::MAX.
 pub struct Slab<T, const N: usize> {
-    // SPMC ring buffer. Contains indices of free slots or usize::MAX if empty.
-    // SlabReceiver is producer in this case, because it pushes returned indices.
-    free_queue: [AtomicUsize; N],
-    // Number of used free_queue slots. Zero means that Slab is empty, N means that Slab is full.
-    free_used: AtomicUsize,
-    // Current pop location of free queue
-    free_queue_tail: AtomicUsize,
-    // Slots that store actual data
-    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+    // Index of the first free slot and a tag, which is incremented by every change to prevent ABA
+    free_head: AtomicU64,
+    // Index of the next free slot for each free slot, NONE for the last one
+    free_next: [AtomicU64; N],
+    // Number of used slots. Zero means that Slab is empty, N means that Slab is full.
+    used: AtomicUsize,
+    // Slots that store actual data. Separate cells, so that loom tracks accesses per slot.
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    // Marks slots that contain an item, which must be dropped together with the slab
+    occupied: [AtomicBool; N],
+    // Highest number of used slots
+    high_water: AtomicUsize,
+    // Number of failed inserts
+    failures: AtomicUsize,
 }
 
 unsafe impl<T: Send, const N: usize> Send for Slab<T, N> {}
@@ -35,21 +87,16 @@ unsafe impl<T: Send, const N: usize> Sync for Slab<T, N> {}
 
 impl<T, const N: usize> Slab<T, N> {
     pub fn new() -> Self {
-        let free_queue = unsafe {
-            let mut buf = MaybeUninit::uninit();
-            let buf_ptr = buf.as_mut_ptr() as *mut AtomicUsize;
-            (0..N).for_each(|i| buf_ptr.add(i).write(AtomicUsize::new(i)));
-            buf.assume_init()
-        };
-
-        // Need unsafe, otherwise compiler complains about T not being copy
-        let slots = unsafe { MaybeUninit::<[MaybeUninit<T>; N]>::uninit().assume_init() };
-
         Self {
-            free_queue,
-            free_used: AtomicUsize::new(0),
-            free_queue_tail: AtomicUsize::new(0),
-            slots: UnsafeCell::new(slots),
+            free_head: AtomicU64::new(if N > 0 { 0 } else { NONE }),
+            free_next: std::array::from_fn(|i| {
+                AtomicU64::new(if i + 1 < N { i as u64 + 1 } else { NONE })
+            }),
+            used: AtomicUsize::new(0),
+            slots: [(); N].map(|_| UnsafeCell::new(MaybeUninit::uninit())),
+            occupied: [(); N].map(|_| AtomicBool::new(false)),
+            high_water: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
         }
     }
 
@@ -60,73 +107,181 @@ impl<T, const N: usize> Slab<T, N> {
             SlabSender {
                 inner: inner.clone(),
             },
-            SlabReceiver {
-                inner,
-                free_queue_head: 0,
-            },
+            SlabReceiver { inner },
         )
     }
-}
 
-#[derive(Clone)]
-pub struct SlabSender<T, const N: usize> {
-    inner: Arc<Slab<T, N>>,
-}
-
-impl<T, const N: usize> SlabSender<T, N> {
-    pub fn insert(&self, item: T) -> Result<SlabHandle, T> {
+    /// Moves `item` into a free slot. Returns `item` back if all slots are used.
+    pub(crate) fn insert(&self, item: T) -> Result<SlabHandle, T> {
         let index = match self.get_index() {
             Some(index) => index,
-            None => return Err(item),
+            None => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(item);
+            }
         };
 
-        unsafe {
-            (*self.inner.slots.get())[index].write(item);
-        }
+        self.slots[index].with_mut(|slot| unsafe {
+            (*slot).write(item);
+        });
+        self.occupied[index].store(true, Ordering::Release);
 
         Ok(SlabHandle {
             index,
-            ptr: Arc::as_ptr(&self.inner) as usize,
+            ptr: self as *const Self as usize,
         })
     }
 
+    /// Moves the item out of its slot and frees the slot, can be called from any thread
+    pub(crate) fn remove(&self, handle: SlabHandle) -> T {
+        // Ensure handle belongs to this slab
+        assert!(self as *const Self as usize == handle.ptr);
+
+        let index = handle.index;
+        // Handle is consumed, so it must not report a leak
+        mem::forget(handle);
+
+        assert!(self.occupied[index].swap(false, Ordering::Acquire));
+        let item = self.slots[index].with(|slot| unsafe { (*slot).as_ptr().read() });
+        self.return_index(index);
+        item
+    }
+
+    /// Returns a pointer to the item of `handle`, which is valid until the handle is removed
+    pub(crate) fn get(&self, handle: &SlabHandle) -> *mut T {
+        assert!(self as *const Self as usize == handle.ptr);
+        self.slots[handle.index].with_mut(|slot| slot as *mut T)
+    }
+
+    /// Returns the number of used slots
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
     fn get_index(&self) -> Option<usize> {
-        let free_used = self.inner.free_used.fetch_add(1, Ordering::Acquire);
-        if free_used >= N {
-            self.inner.free_used.fetch_sub(1, Ordering::Release);
-            return None;
+        let mut head = self.free_head.load(Ordering::Acquire);
+        let index = loop {
+            let index = head & INDEX_MASK;
+            if index == NONE {
+                return None;
+            }
+
+            // May be stale if the slot is taken and freed meanwhile, then the tag differs
+            let next = self.free_next[index as usize].load(Ordering::Relaxed);
+            let new = (head & !INDEX_MASK).wrapping_add(TAG) | next;
+            match self.free_head.compare_exchange_weak(
+                head,
+                new,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break index as usize,
+                Err(actual) => head = actual,
+            }
+        };
+
+        let used = self.used.fetch_add(1, Ordering::Relaxed) + 1;
+        self.high_water.fetch_max(used, Ordering::Relaxed);
+        Some(index)
+    }
+
+    fn return_index(&self, index: usize) {
+        self.used.fetch_sub(1, Ordering::Relaxed);
+
+        let mut head = self.free_head.load(Ordering::Relaxed);
+        loop {
+            self.free_next[index].store(head & INDEX_MASK, Ordering::Relaxed);
+            let new = (head & !INDEX_MASK).wrapping_add(TAG) | index as u64;
+            match self.free_head.compare_exchange_weak(
+                head,
+                new,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
         }
+    }
+}
 
-        let tail = self.inner.free_queue_tail.fetch_add(1, Ordering::Acquire);
-        let val = self.inner.free_queue[tail % N].swap(usize::MAX, Ordering::Release);
-        assert!(val != usize::MAX);
-        Some(val)
+impl<T, const N: usize> Default for Slab<T, N> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-pub struct SlabReceiver<T, const N: usize> {
+impl<T, const N: usize> Drop for Slab<T, N> {
+    fn drop(&mut self) {
+        let mut unconsumed = 0;
+
+        for (slot, occupied) in self.slots.iter().zip(self.occupied.iter()) {
+            if occupied.load(Ordering::Acquire) {
+                slot.with_mut(|slot| unsafe { (*slot).assume_init_drop() });
+                unconsumed += 1;
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        if unconsumed > 0 {
+            eprintln!(
+                "rtic: slab dropped with {} unconsumed item(s) of type `{}`",
+                unconsumed,
+                std::any::type_name::<T>()
+            );
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = unconsumed;
+    }
+}
+
+#[derive(Clone)]
+pub struct SlabSender<T, const N: usize> {
     inner: Arc<Slab<T, N>>,
-    // push location of free_queue
-    free_queue_head: usize,
 }
 
-impl<T, const N: usize> SlabReceiver<T, N> {
-    pub fn remove(&mut self, handle: SlabHandle) -> T {
-        // Ensure handle belongs to this slab
-        assert!(Arc::as_ptr(&self.inner) as usize == handle.ptr);
+impl<T, const N: usize> SlabSender<T, N> {
+    pub fn insert(&self, item: T) -> Result<SlabHandle, T> {
+        self.inner.insert(item)
+    }
 
-        let item = unsafe { (*self.inner.slots.get())[handle.index].as_ptr().read() };
-        self.return_index(handle.index);
-        item
+    /// Returns the number of used slots
+    pub fn used(&self) -> usize {
+        self.inner.used()
     }
 
-    fn return_index(&mut self, index: usize) {
-        let old = self.inner.free_queue[self.free_queue_head % N].swap(index, Ordering::Acquire);
-        assert!(old == usize::MAX);
+    /// Returns the number of slots
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the highest number of used slots so far
+    pub fn high_water(&self) -> usize {
+        self.inner.high_water.load(Ordering::Relaxed)
+    }
 
-        let count = self.inner.free_used.fetch_sub(1, Ordering::Release);
-        assert!(count != usize::MAX);
+    /// Returns the number of inserts that failed because all slots were used
+    pub fn failures(&self) -> usize {
+        self.inner.failures.load(Ordering::Relaxed)
+    }
+}
+
+pub struct SlabReceiver<T, const N: usize> {
+    inner: Arc<Slab<T, N>>,
+}
+
+impl<T, const N: usize> SlabReceiver<T, N> {
+    pub fn remove(&mut self, handle: SlabHandle) -> T {
+        self.inner.remove(handle)
+    }
+}
 
-        self.free_queue_head += 1;
+impl Drop for SlabHandle {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        eprintln!(
+            "rtic: slab handle to slot {} was dropped without being removed, the slot is leaked",
+            self.index
+        );
     }
 }
@@ -0,0 +1,320 @@
+//! Per-task timing statistics
+//!
+//! With the `stats` feature every task run is recorded into lock-free histograms:
+//!
+//! - latency: time from release (`spawn` or the scheduled instant) until the task starts
+//! - wall time: execution time measured with the monotonic clock, including preemption
+//! - CPU time: execution time measured with `CLOCK_THREAD_CPUTIME_ID`
+//!
+//! Locks of shared resources record the time until the resource is acquired and the time it is
+//! held.
+//!
+//! Statistics are available at runtime with `AppHandle::stats` and are printed on shutdown. Without
+//! the feature nothing is measured and histograms stay empty.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Whether statistics are collected
+pub const ENABLED: bool = cfg!(feature = "stats");
+
+// Values below 4ns have their own bucket, then 4 buckets per power of two
+const SUB_BUCKETS: u32 = 4;
+const SUB_BUCKET_BITS: u32 = 2;
+const BUCKETS: usize = (SUB_BUCKETS + (64 - SUB_BUCKET_BITS) * SUB_BUCKETS) as usize;
+
+/// Fixed-size lock-free histogram of durations with 4 buckets per power of two (<25% error)
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a duration
+    pub fn record(&self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(nanos, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of recorded durations
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the longest recorded duration
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max.load(Ordering::Relaxed))
+    }
+
+    /// Returns the sum of recorded durations
+    pub fn sum(&self) -> Duration {
+        Duration::from_nanos(self.sum.load(Ordering::Relaxed))
+    }
+
+    /// Returns the mean of recorded durations
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos(self.sum.load(Ordering::Relaxed) / count),
+        }
+    }
+
+    /// Returns an upper bound of the `p`-th percentile, i.e. `0.99`
+    pub fn percentile(&self, p: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+
+        let target = ((count as f64 * p).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= target {
+                // Bucket bound may be above the actual maximum
+                return Duration::from_nanos(upper_bound(index)).min(self.max());
+            }
+        }
+
+        self.max()
+    }
+
+    /// Clears all recorded durations
+    pub fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.sum.store(0, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Histogram")
+            .field("count", &self.count())
+            .field("mean", &self.mean())
+            .field("p99", &self.percentile(0.99))
+            .field("max", &self.max())
+            .finish()
+    }
+}
+
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+
+    let exp = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (exp - SUB_BUCKET_BITS)) as u32 & (SUB_BUCKETS - 1);
+    (SUB_BUCKETS + (exp - SUB_BUCKET_BITS) * SUB_BUCKETS + sub) as usize
+}
+
+fn upper_bound(index: usize) -> u64 {
+    let index = index as u32;
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let exp = (index - SUB_BUCKETS) / SUB_BUCKETS;
+    let sub = (index - SUB_BUCKETS) % SUB_BUCKETS;
+    let bound = ((SUB_BUCKETS + sub + 1) as u128) << exp;
+    (bound - 1).min(u64::MAX as u128) as u64
+}
+
+/// Statistics of a single task
+#[derive(Debug)]
+pub struct TaskStats {
+    /// Name of the task
+    pub name: &'static str,
+    /// Priority of the task
+    pub priority: u8,
+    /// Time from release until the task starts
+    pub latency: Histogram,
+    /// Execution time measured with the monotonic clock
+    pub wall_time: Histogram,
+    /// Execution time measured with the thread CPU clock
+    pub cpu_time: Histogram,
+}
+
+/// Start of a task run, returned by [`TaskStats::start`]
+#[doc(hidden)]
+pub struct Timer {
+    started_at: Option<Instant>,
+    cpu_time: Duration,
+}
+
+impl TaskStats {
+    pub const fn new(name: &'static str, priority: u8) -> Self {
+        Self {
+            name,
+            priority,
+            latency: Histogram::new(),
+            wall_time: Histogram::new(),
+            cpu_time: Histogram::new(),
+        }
+    }
+
+    /// Called by the dispatcher before a task runs
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn start(&self, released_at: Option<Instant>) -> Timer {
+        if !ENABLED {
+            return Timer {
+                started_at: None,
+                cpu_time: Duration::ZERO,
+            };
+        }
+
+        let now = Instant::now();
+        if let Some(released_at) = released_at {
+            self.latency
+                .record(now.saturating_duration_since(released_at));
+        }
+
+        Timer {
+            started_at: Some(now),
+            cpu_time: thread_cpu_time(),
+        }
+    }
+
+    /// Called by the dispatcher after a task returns
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn finish(&self, timer: Timer) {
+        if let Some(started_at) = timer.started_at {
+            self.cpu_time
+                .record(thread_cpu_time().saturating_sub(timer.cpu_time));
+            self.wall_time.record(started_at.elapsed());
+        }
+    }
+}
+
+/// Lock statistics of a shared resource
+#[derive(Debug)]
+pub struct LockStats {
+    /// Name of the resource
+    pub name: &'static str,
+    /// Time from `lock` until the resource is acquired
+    pub wait: Histogram,
+    /// Time the resource is held
+    pub held: Histogram,
+}
+
+impl LockStats {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            wait: Histogram::new(),
+            held: Histogram::new(),
+        }
+    }
+
+    /// Called before the resource is locked
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn request(&self) -> Option<Instant> {
+        if ENABLED {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Called once the resource is locked
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn acquired(&self, requested_at: Option<Instant>) -> Option<Instant> {
+        requested_at.map(|requested_at| {
+            let now = Instant::now();
+            self.wait
+                .record(now.saturating_duration_since(requested_at));
+            now
+        })
+    }
+
+    /// Called after the resource is unlocked
+    #[doc(hidden)]
+    #[inline(always)]
+    pub fn release(&self, acquired_at: Option<Instant>) {
+        if let Some(acquired_at) = acquired_at {
+            self.held.record(acquired_at.elapsed());
+        }
+    }
+}
+
+/// Returns the time of an immediate release, if statistics are collected
+#[doc(hidden)]
+#[inline(always)]
+pub fn release_time() -> Option<Instant> {
+    if ENABLED {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+fn thread_cpu_time() -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Prints statistics of all tasks as a table
+pub fn print(tasks: &[&TaskStats]) {
+    if !ENABLED || tasks.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "{:<20} {:>4} {:>8}  {:>27}  {:>27}  {:>27}",
+        "task", "prio", "count", "latency p50/p99/max", "wall p50/p99/max", "cpu p50/p99/max"
+    );
+    for task in tasks {
+        eprintln!(
+            "{:<20} {:>4} {:>8}  {:>27}  {:>27}  {:>27}",
+            task.name,
+            task.priority,
+            task.wall_time.count(),
+            summary(&task.latency),
+            summary(&task.wall_time),
+            summary(&task.cpu_time),
+        );
+    }
+}
+
+fn summary(histogram: &Histogram) -> String {
+    format!(
+        "{:?}/{:?}/{:?}",
+        histogram.percentile(0.5),
+        histogram.percentile(0.99),
+        histogram.max()
+    )
+}
@@ -0,0 +1,136 @@
+//! systemd service notifications
+//!
+//! With the `systemd` feature, applications can run as `Type=notify` services:
+//!
+//! - `READY=1` is sent once all dispatcher threads are initialized
+//! - `STOPPING=1` is sent when shutdown is requested
+//! - `WATCHDOG=1` is sent every half of `WatchdogSec=` as long as no dispatcher is stalled, see
+//!   [`ProgressSnapshot::is_live`]
+//!
+//! Notifications are sent to the `NOTIFY_SOCKET` datagram socket set by systemd. Without the
+//! feature or the socket, all functions do nothing.
+
+use std::{
+    env, io,
+    os::unix::net::{SocketAddr, UnixDatagram},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::runtime::{Progress, ProgressSnapshot};
+
+/// Sends a notification, i.e. `STATUS=Connecting`
+///
+/// Returns `false` if notifications are disabled.
+pub fn notify(state: &str) -> io::Result<bool> {
+    if !cfg!(feature = "systemd") {
+        return Ok(false);
+    }
+
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+
+    let addr = match path.as_encoded_bytes() {
+        [b'@', name @ ..] => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name)?
+        }
+        _ => SocketAddr::from_pathname(&path)?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+/// Sends a free-form status message, shown by `systemctl status`
+pub fn status(message: &str) -> io::Result<bool> {
+    notify(&format!("STATUS={}", message))
+}
+
+/// Returns the interval at which systemd expects `WATCHDOG=1`, if the watchdog is enabled
+pub fn watchdog_interval() -> Option<Duration> {
+    if !cfg!(feature = "systemd") {
+        return None;
+    }
+
+    // Watchdog may be meant for another process, i.e. the parent shell
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|&usec| usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// Notifies readiness and starts the watchdog thread
+///
+/// Called by the generated code once all dispatcher threads are initialized.
+#[doc(hidden)]
+pub fn ready(name: &str, progress: Vec<&'static Progress>, shutdown: &'static AtomicBool) {
+    report(notify(&format!(
+        "READY=1\n{}",
+        running_status(progress.len())
+    )));
+
+    if let Some(interval) = watchdog_interval() {
+        let result = crate::runtime::spawn_thread(name, None, move || {
+            watchdog(interval / 2, progress, shutdown)
+        });
+        // systemd restarts the service once the watchdog timeout expires
+        if let Err(err) = result {
+            eprintln!("rtic: failed to pet systemd watchdog: {}", err);
+        }
+    }
+}
+
+/// Notifies that the application is stopping
+#[doc(hidden)]
+pub fn stopping() {
+    report(notify("STOPPING=1\nSTATUS=Stopping"));
+}
+
+/// Pets the watchdog as long as all dispatchers make progress
+fn watchdog(period: Duration, progress: Vec<&'static Progress>, shutdown: &'static AtomicBool) {
+    // Runs below all tasks, so that starvation stops the watchdog too
+    crate::init_thread_state(0);
+
+    let mut prev: Vec<ProgressSnapshot> = progress.iter().map(|p| p.snapshot()).collect();
+    let mut stalled = false;
+
+    while !shutdown.load(Ordering::Relaxed) {
+        std::thread::sleep(period);
+
+        let current: Vec<ProgressSnapshot> = progress.iter().map(|p| p.snapshot()).collect();
+        let live = current.iter().zip(&prev).all(|(c, p)| c.is_live(p));
+        prev = current;
+
+        if live {
+            report(notify("WATCHDOG=1"));
+            if stalled {
+                report(notify(&running_status(progress.len())));
+            }
+        } else if !stalled {
+            report(status("Dispatcher thread stalled"));
+        }
+        stalled = !live;
+    }
+}
+
+fn running_status(threads: usize) -> String {
+    format!("STATUS=Running {} dispatcher thread(s)", threads)
+}
+
+fn report(result: io::Result<bool>) {
+    if let Err(err) = result {
+        eprintln!("rtic: failed to notify systemd: {}", err);
+    }
+}
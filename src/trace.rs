@@ -0,0 +1,705 @@
+//! Binary trace of task executions in the Fuchsia trace format
+//!
+//! With the `trace` feature every thread records events into its own lock-free buffer, which a
+//! low priority writer thread drains into a file. The file loads directly in
+//! [ui.perfetto.dev](https://ui.perfetto.dev):
+//!
+//! - each thread is a track, named after the thread and its priority
+//! - task executions and held locks are slices
+//! - spawns are flows from the spawning slice to the task execution
+//! - lock waits are slices, a task that releases a lock another task waits for starts a flow from
+//!   its lock slice to the lock slice of the next holder
+//!
+//! The file is written to `$RTIC_TRACE` or `rtic-<pid>.fxt` in the working directory. Events are
+//! dropped and counted if a buffer fills up faster than it is written, the count is printed when the
+//! application stops. Without the feature nothing is recorded.
+//!
+//! See <https://fuchsia.dev/fuchsia-src/reference/tracing/trace-format> for the format.
+//!
+//! Slices are also written to the kernel trace by the `ftrace` feature, see [`crate::ftrace`].
+
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{
+    ring::{self, Ring, Rings},
+    runtime::monotonic_nanos,
+};
+
+/// Whether events are recorded
+pub const ENABLED: bool = cfg!(feature = "trace");
+
+/// Number of events each thread can record between writes
+const CAPACITY: usize = 1 << 14;
+
+/// Interval at which buffers are written
+const PERIOD: Duration = Duration::from_millis(10);
+
+/// Category of all events
+const CATEGORY: &str = "rtic";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Begin,
+    End,
+    FlowBegin,
+    FlowEnd,
+}
+
+#[derive(Clone, Copy)]
+struct Event {
+    timestamp: u64,
+    kind: Kind,
+    name: &'static str,
+    flow: u64,
+}
+
+const EMPTY: Event = Event {
+    timestamp: 0,
+    kind: Kind::End,
+    name: "",
+    flow: 0,
+};
+
+thread_local! {
+    static BUFFER: OnceCell<ring::Local<Event>> = const { OnceCell::new() };
+}
+
+/// Buffers of all threads that recorded an event
+static BUFFERS: Rings<Event> = Rings::new(&BUFFER, CAPACITY, EMPTY);
+static STOP: AtomicBool = AtomicBool::new(false);
+static WRITER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static NEXT_FLOW: AtomicU64 = AtomicU64::new(1 << 63);
+
+/// Allocates the buffer of the current thread, so that recording does not allocate
+///
+/// Called by [`crate::init_thread_state`], other threads are registered on their first event.
+#[doc(hidden)]
+pub fn register_thread(priority: u8) {
+    if ENABLED {
+        BUFFERS.with(priority, |_| {});
+    }
+}
+
+#[inline(always)]
+fn record(kind: Kind, name: &'static str, flow: u64) {
+    if ENABLED {
+        BUFFERS.with(0, |buffer| {
+            buffer.push(Event {
+                timestamp: monotonic_nanos(),
+                kind,
+                name,
+                flow,
+            })
+        });
+    }
+}
+
+/// Starts a slice on the current thread
+#[doc(hidden)]
+#[inline(always)]
+pub fn begin(name: &'static str) {
+    crate::ftrace::begin(name);
+    record(Kind::Begin, name, 0);
+}
+
+/// Ends the slice started by [`begin`]
+#[doc(hidden)]
+#[inline(always)]
+pub fn end(name: &'static str) {
+    crate::ftrace::end();
+    record(Kind::End, name, 0);
+}
+
+/// Starts a flow from the current slice
+#[doc(hidden)]
+#[inline(always)]
+pub fn flow_begin(name: &'static str, flow: u64) {
+    record(Kind::FlowBegin, name, flow);
+}
+
+/// Ends a flow at the current slice
+#[doc(hidden)]
+#[inline(always)]
+pub fn flow_end(name: &'static str, flow: u64) {
+    record(Kind::FlowEnd, name, flow);
+}
+
+/// Returns a flow identifier of a task instance, which is unique while the instance is queued
+#[doc(hidden)]
+#[inline(always)]
+pub fn task_flow(task: u32, slot: usize) -> u64 {
+    (task as u64 + 1) << 32 | slot as u64
+}
+
+/// Returns a new flow identifier, distinct from [`task_flow`]
+#[doc(hidden)]
+#[inline(always)]
+pub fn next_flow() -> u64 {
+    if ENABLED {
+        NEXT_FLOW.fetch_add(1, Ordering::Relaxed)
+    } else {
+        0
+    }
+}
+
+/// Flow from the task that releases a shared resource to the next task that waited for it
+///
+/// The releasing task only starts a flow if another task waits at that moment, the flow ends in
+/// the lock slice of whichever task acquires the resource next.
+#[doc(hidden)]
+pub struct LockFlow {
+    waiters: AtomicU32,
+    // Flow started by the last release and not ended yet, 0 if there is none
+    handoff: AtomicU64,
+}
+
+impl LockFlow {
+    pub const fn new() -> Self {
+        Self {
+            waiters: AtomicU32::new(0),
+            handoff: AtomicU64::new(0),
+        }
+    }
+
+    /// Called before the resource is locked
+    #[inline(always)]
+    pub fn wait(&self) {
+        if ENABLED {
+            self.waiters.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Called once the resource is locked, inside the lock slice
+    #[inline(always)]
+    pub fn acquired(&self, name: &'static str) {
+        if ENABLED {
+            self.waiters.fetch_sub(1, Ordering::Relaxed);
+            // Ordered with `release` by the resource mutex
+            match self.handoff.swap(0, Ordering::Relaxed) {
+                0 => {}
+                flow => flow_end(name, flow),
+            }
+        }
+    }
+
+    /// Called before the resource is unlocked, inside the lock slice
+    #[inline(always)]
+    pub fn release(&self, name: &'static str) {
+        if ENABLED && self.waiters.load(Ordering::Relaxed) > 0 {
+            let flow = next_flow();
+            self.handoff.store(flow, Ordering::Relaxed);
+            flow_begin(name, flow);
+        }
+    }
+}
+
+impl Default for LockFlow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Starts the writer thread and opens the kernel trace
+///
+/// Called by the generated code before `#[init]`.
+#[doc(hidden)]
+pub fn start(name: &str) {
+    crate::ftrace::open();
+    if !ENABLED {
+        return;
+    }
+
+    let path = std::env::var_os("RTIC_TRACE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| format!("rtic-{}.fxt", std::process::id()).into());
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("rtic: trace: failed to create {}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    let result = crate::runtime::spawn_thread(name, None, move || {
+        // Below all tasks, writing must not delay them
+        crate::sched::init_thread(0);
+
+        let mut writer = Writer::new(BufWriter::new(file));
+        let result = writer.run();
+        if let Err(err) = result {
+            eprintln!("rtic: trace: failed to write {}: {}", path.display(), err);
+        }
+    });
+    match result {
+        Ok(handle) => *WRITER.lock().unwrap() = Some(handle),
+        // Tracing is a diagnostic, the application runs without it
+        Err(err) => eprintln!("rtic: trace: {}", err),
+    }
+}
+
+/// Writes remaining events and stops the writer thread
+///
+/// Called when the application stops.
+#[doc(hidden)]
+pub fn stop() {
+    let handle = match WRITER.lock().unwrap().take() {
+        Some(handle) => handle,
+        None => return,
+    };
+
+    STOP.store(true, Ordering::Relaxed);
+    handle.join().ok();
+
+    let dropped = BUFFERS.dropped();
+    if dropped > 0 {
+        eprintln!("rtic: trace: {} events dropped", dropped);
+    }
+}
+
+/// Reference to an interned string, or an inline string if the table is full
+#[derive(Clone, Copy)]
+enum StringRef {
+    Index(u16),
+    Inline(&'static str),
+}
+
+impl StringRef {
+    fn field(self) -> u64 {
+        match self {
+            StringRef::Index(index) => index as u64,
+            StringRef::Inline(s) => 0x8000 | s.len() as u64,
+        }
+    }
+
+    fn words(self) -> usize {
+        match self {
+            StringRef::Index(_) => 0,
+            StringRef::Inline(s) => padded_words(s.len()),
+        }
+    }
+}
+
+const MAX_STRING_INDEX: u16 = 0x7fff;
+const MAX_THREAD_INDEX: u8 = 0xff;
+
+/// Encodes buffered events into Fuchsia trace records
+struct Writer<W: Write> {
+    out: W,
+    words: Vec<u64>,
+    strings: HashMap<(usize, usize), u16>,
+    // Thread reference of each buffer, by its id
+    threads: HashMap<u64, Option<u8>>,
+    // Next thread reference, not reused when buffers of exited threads are freed
+    next_thread: usize,
+    pid: u64,
+}
+
+impl<W: Write> Writer<W> {
+    fn new(out: W) -> Self {
+        Self {
+            out,
+            words: Vec::new(),
+            strings: HashMap::new(),
+            threads: HashMap::new(),
+            next_thread: 1,
+            pid: std::process::id() as u64,
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let name = std::env::current_exe()
+            .ok()
+            .and_then(|path| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "rtic".to_owned());
+        self.header(Box::leak(name.into_boxed_str()));
+
+        loop {
+            let stop = STOP.load(Ordering::Relaxed);
+
+            self.drain();
+            self.flush()?;
+            if stop {
+                return self.out.flush();
+            }
+            std::thread::sleep(PERIOD);
+        }
+    }
+
+    /// Encodes the events of all buffers
+    fn drain(&mut self) {
+        let buffers = BUFFERS.buffers();
+        for buffer in &buffers {
+            let thread = match self.threads.get(&buffer.id) {
+                Some(&thread) => thread,
+                None => {
+                    let thread = self.thread(buffer);
+                    self.threads.insert(buffer.id, thread);
+                    thread
+                }
+            };
+            buffer.drain(|event| self.event(thread, buffer.tid, event));
+        }
+
+        // Buffers of exited threads are freed after their last drain
+        self.threads
+            .retain(|id, _| buffers.iter().any(|buffer| buffer.id == *id));
+    }
+
+    /// Writes the magic number, initialization and process records
+    fn header(&mut self, process: &'static str) {
+        // Magic number record
+        self.words.push(0x0016_5478_4604_0010);
+        // Initialization record, timestamps are in nanoseconds
+        self.words.push(1 | 2 << 4);
+        self.words.push(1_000_000_000);
+
+        let name = self.string(process);
+        self.kernel_object(1, self.pid, name, None);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for word in self.words.drain(..) {
+            self.out.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Interns a string, writing a string record the first time it is seen
+    fn string(&mut self, s: &'static str) -> StringRef {
+        let key = (s.as_ptr() as usize, s.len());
+        if let Some(&index) = self.strings.get(&key) {
+            return StringRef::Index(index);
+        }
+
+        let index = self.strings.len() as u16 + 1;
+        if index > MAX_STRING_INDEX || s.len() > MAX_STRING_INDEX as usize {
+            return StringRef::Inline(s);
+        }
+        self.strings.insert(key, index);
+
+        let size = 1 + padded_words(s.len()) as u64;
+        self.words
+            .push(2 | size << 4 | (index as u64) << 16 | (s.len() as u64) << 32);
+        self.push_str(s);
+        StringRef::Index(index)
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for chunk in s.as_bytes().chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.words.push(u64::from_le_bytes(word));
+        }
+    }
+
+    /// Writes thread and kernel object records of a buffer, returns its thread reference
+    fn thread(&mut self, buffer: &Ring<Event>) -> Option<u8> {
+        let name = format!("{} (priority {})", buffer.name, buffer.priority);
+        let name = self.string(Box::leak(name.into_boxed_str()));
+        self.kernel_object(2, buffer.tid, name, Some(self.pid));
+
+        let index = self.next_thread;
+        if index > MAX_THREAD_INDEX as usize {
+            return None;
+        }
+        self.next_thread += 1;
+        self.words.push(3 | 3 << 4 | (index as u64) << 16);
+        self.words.push(self.pid);
+        self.words.push(buffer.tid);
+        Some(index as u8)
+    }
+
+    /// Writes a kernel object record naming a process (`1`) or thread (`2`)
+    fn kernel_object(&mut self, ty: u64, koid: u64, name: StringRef, process: Option<u64>) {
+        let process_arg = process.map(|koid| (self.string("process"), koid));
+        let args = process_arg.iter().count() as u64;
+        let size =
+            2 + name.words() as u64 + process_arg.map_or(0, |(arg, _)| 2 + arg.words() as u64);
+
+        self.words
+            .push(7 | size << 4 | ty << 16 | name.field() << 24 | args << 40);
+        self.words.push(koid);
+        if let StringRef::Inline(s) = name {
+            self.push_str(s);
+        }
+        if let Some((arg, koid)) = process_arg {
+            // Koid argument
+            self.words
+                .push(8 | (2 + arg.words() as u64) << 4 | arg.field() << 16);
+            if let StringRef::Inline(s) = arg {
+                self.push_str(s);
+            }
+            self.words.push(koid);
+        }
+    }
+
+    fn event(&mut self, thread: Option<u8>, tid: u64, event: &Event) {
+        let category = self.string(CATEGORY);
+        let name = self.string(event.name);
+        let ty: u64 = match event.kind {
+            Kind::Begin => 2,
+            Kind::End => 3,
+            Kind::FlowBegin => 8,
+            Kind::FlowEnd => 10,
+        };
+        let flow = matches!(event.kind, Kind::FlowBegin | Kind::FlowEnd);
+
+        let size = 2 + thread.map_or(2, |_| 0) + category.words() + name.words() + flow as usize;
+        self.words.push(
+            4 | (size as u64) << 4
+                | ty << 16
+                | (thread.unwrap_or(0) as u64) << 24
+                | category.field() << 32
+                | name.field() << 48,
+        );
+        self.words.push(event.timestamp);
+        if thread.is_none() {
+            self.words.push(self.pid);
+            self.words.push(tid);
+        }
+        for string in [category, name] {
+            if let StringRef::Inline(s) = string {
+                self.push_str(s);
+            }
+        }
+        if flow {
+            self.words.push(event.flow);
+        }
+    }
+}
+
+fn padded_words(len: usize) -> usize {
+    len.div_ceil(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Record split into its header and the words that follow it
+    struct Record {
+        header: u64,
+        body: Vec<u64>,
+    }
+
+    impl Record {
+        fn ty(&self) -> u64 {
+            self.field(0, 4)
+        }
+
+        fn field(&self, lsb: u32, bits: u32) -> u64 {
+            self.header >> lsb & ((1 << bits) - 1)
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<Record> {
+        assert_eq!(bytes.len() % 8, 0);
+        let words: Vec<u64> = bytes
+            .chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        let mut records = vec![];
+        let mut pos = 0;
+        while pos < words.len() {
+            let header = words[pos];
+            let size = (header >> 4 & 0xfff) as usize;
+            assert!(size > 0 && pos + size <= words.len(), "bad record size");
+            records.push(Record {
+                header,
+                body: words[pos + 1..pos + size].to_vec(),
+            });
+            pos += size;
+        }
+        records
+    }
+
+    fn text(words: &[u64], len: usize) -> String {
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        String::from_utf8(bytes[..len].to_vec()).unwrap()
+    }
+
+    /// Decoded event record with resolved strings
+    #[derive(Debug, PartialEq)]
+    struct Decoded {
+        ty: u64,
+        thread: u64,
+        category: String,
+        name: String,
+        body: Vec<u64>,
+    }
+
+    /// Resolves interned strings and returns the event records
+    fn events(records: &[Record]) -> Vec<Decoded> {
+        let mut strings = HashMap::new();
+        let mut events = vec![];
+        for record in records {
+            match record.ty() {
+                2 => {
+                    let len = record.field(32, 15) as usize;
+                    strings.insert(record.field(16, 15), text(&record.body, len));
+                }
+                4 => events.push(Decoded {
+                    ty: record.field(16, 4),
+                    thread: record.field(24, 8),
+                    category: strings[&record.field(32, 16)].clone(),
+                    name: strings[&record.field(48, 16)].clone(),
+                    body: record.body.clone(),
+                }),
+                _ => {}
+            }
+        }
+        events
+    }
+
+    fn event(timestamp: u64, kind: Kind, name: &'static str, flow: u64) -> Event {
+        Event {
+            timestamp,
+            kind,
+            name,
+            flow,
+        }
+    }
+
+    #[test]
+    fn header() {
+        let mut writer = Writer::new(Vec::new());
+        writer.header("app");
+        writer.flush().unwrap();
+        let records = decode(&writer.out);
+
+        assert_eq!(records[0].header, 0x0016_5478_4604_0010);
+        assert_eq!(records[1].ty(), 1);
+        assert_eq!(records[1].body, [1_000_000_000]);
+
+        // Process name is interned first
+        assert_eq!(records[2].ty(), 2);
+        assert_eq!(records[2].field(16, 15), 1);
+        assert_eq!(
+            text(&records[2].body, records[2].field(32, 15) as usize),
+            "app"
+        );
+
+        assert_eq!(records[3].ty(), 7);
+        assert_eq!(records[3].field(16, 8), 1);
+        assert_eq!(records[3].field(24, 16), 1);
+        assert_eq!(records[3].field(40, 4), 0);
+        assert_eq!(records[3].body, [std::process::id() as u64]);
+        assert_eq!(records.len(), 4);
+
+        // Strings are interned once
+        writer.string("app");
+        assert!(writer.words.is_empty());
+    }
+
+    #[test]
+    fn thread_events() {
+        let buffer = Ring::new(1, EMPTY, 0, "app-2".into(), 42, 2);
+        let mut writer = Writer::new(Vec::new());
+        let pid = std::process::id() as u64;
+
+        let thread = writer.thread(&buffer);
+        assert_eq!(thread, Some(1));
+        let flow = task_flow(1, 3);
+        writer.event(thread, 42, &event(100, Kind::Begin, "tick", 0));
+        writer.event(thread, 42, &event(110, Kind::FlowEnd, "spawn tick", flow));
+        writer.event(thread, 42, &event(120, Kind::End, "tick", 0));
+        // Thread table is full, thread is written inline
+        writer.event(None, 42, &event(130, Kind::FlowBegin, "spawn tick", flow));
+        writer.flush().unwrap();
+        let records = decode(&writer.out);
+
+        let thread_record = records.iter().find(|r| r.ty() == 3).unwrap();
+        assert_eq!(thread_record.field(16, 8), 1);
+        assert_eq!(thread_record.body, [pid, 42]);
+
+        let object = records.iter().find(|r| r.ty() == 7).unwrap();
+        // Thread object with a `process` koid argument
+        assert_eq!(object.field(16, 8), 2);
+        assert_eq!(object.field(40, 4), 1);
+        assert_eq!(object.body[0], 42);
+        assert_eq!(object.body[2], pid);
+
+        let decoded = |ty, thread, name: &str, body: Vec<u64>| Decoded {
+            ty,
+            thread,
+            category: CATEGORY.into(),
+            name: name.into(),
+            body,
+        };
+        assert_eq!(
+            events(&records),
+            [
+                decoded(2, 1, "tick", vec![100]),
+                decoded(10, 1, "spawn tick", vec![110, flow]),
+                decoded(3, 1, "tick", vec![120]),
+                decoded(8, 0, "spawn tick", vec![130, pid, 42, flow]),
+            ]
+        );
+    }
+
+    #[test]
+    fn flow_ids() {
+        let ids: HashSet<u64> = (0..4)
+            .flat_map(|task| (0..4).map(move |slot| task_flow(task, slot)))
+            .collect();
+        assert_eq!(ids.len(), 16);
+        // Zero means no flow and ids of `next_flow` have the top bit set
+        assert!(ids.iter().all(|&id| id != 0 && id < 1 << 63));
+        assert!(task_flow((u32::MAX >> 1) - 1, usize::MAX >> 32) < 1 << 63);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn lock_flow() {
+        let lock = LockFlow::new();
+        let flows = || {
+            let mut flows = vec![];
+            BUFFERS.with(0, |buffer| {
+                buffer.drain(|event| {
+                    if event.name == "lock r" {
+                        flows.push((event.kind, event.flow));
+                    }
+                })
+            });
+            flows
+        };
+
+        // Uncontended lock does not start a flow
+        lock.wait();
+        lock.acquired("lock r");
+        lock.release("lock r");
+        assert!(flows().is_empty());
+
+        // Holder releases while another task waits, the flow ends where the waiter holds the lock
+        lock.wait();
+        lock.acquired("lock r");
+        lock.wait();
+        lock.release("lock r");
+        lock.acquired("lock r");
+        lock.release("lock r");
+
+        let flows = flows();
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].0, Kind::FlowBegin);
+        assert_eq!(flows[1].0, Kind::FlowEnd);
+        assert_eq!(flows[0].1, flows[1].1);
+        assert!(flows[0].1 >= 1 << 63);
+    }
+}
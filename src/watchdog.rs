@@ -0,0 +1,160 @@
+//! Liveness watchdog of dispatcher threads
+//!
+//! Enabled with `#[rtic::app(watchdog = "100ms")]`. A separate thread periodically checks every
+//! dispatcher and reports a [`Stall`] if:
+//!
+//! - a task runs longer than the configured time
+//! - tasks are released, but the dispatcher does not start any of them for the configured time,
+//!   i.e. it is starved by higher priority threads
+//! - the dispatcher thread has exited, i.e. a task panicked
+//!
+//! Stalls are passed to the function marked with `#[watchdog]` in the app module, or printed
+//! followed by [`std::process::abort`] if there is none.
+
+use std::{
+    fmt, io,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::runtime::{monotonic_nanos, Progress, ProgressSnapshot};
+
+/// A dispatcher thread that does not make progress
+#[derive(Debug, Clone)]
+pub struct Stall {
+    /// OS thread name of the dispatcher
+    pub thread: &'static str,
+    /// Priority of the dispatcher
+    pub priority: u8,
+    pub kind: StallKind,
+}
+
+/// Reason of a [`Stall`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallKind {
+    /// A task has been running for the given time
+    Overrun(Duration),
+    /// Released tasks have not been started for the given time
+    Starved(Duration),
+    /// Dispatcher thread has exited
+    Died,
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            StallKind::Overrun(time) => write!(
+                f,
+                "task on thread {} has been running for {:?}",
+                self.thread, time
+            ),
+            StallKind::Starved(time) => write!(
+                f,
+                "thread {} has not started released tasks for {:?}",
+                self.thread, time
+            ),
+            StallKind::Died => write!(f, "thread {} has exited", self.thread),
+        }
+    }
+}
+
+/// Dispatcher thread checked by the watchdog
+#[doc(hidden)]
+pub struct Dispatcher {
+    pub name: &'static str,
+    pub priority: u8,
+    pub progress: &'static Progress,
+}
+
+/// Default handler, which prints the stall and aborts the process
+pub fn abort(stall: &Stall) {
+    eprintln!("rtic: watchdog: {}", stall);
+    std::process::abort();
+}
+
+/// Spawns the watchdog thread
+#[doc(hidden)]
+pub fn spawn(
+    name: &str,
+    timeout: Duration,
+    dispatchers: Vec<Dispatcher>,
+    shutdown: &'static AtomicBool,
+    handler: fn(&Stall),
+) -> io::Result<()> {
+    crate::runtime::spawn_thread(name, None, move || {
+        run(timeout, dispatchers, shutdown, handler)
+    })
+    .map(drop)
+}
+
+/// Per dispatcher state of the watchdog
+struct State {
+    prev: ProgressSnapshot,
+    // Time when `started` last changed
+    progressed_at: u64,
+    // Stall is reported once until the dispatcher recovers
+    reported: bool,
+}
+
+fn run(
+    timeout: Duration,
+    dispatchers: Vec<Dispatcher>,
+    shutdown: &'static AtomicBool,
+    handler: fn(&Stall),
+) {
+    // At the highest priority of the app, otherwise dispatchers would starve the watchdog too
+    crate::init_thread_state(crate::sched::max_priority());
+
+    let period = (timeout / 4).max(Duration::from_millis(1));
+    let now = monotonic_nanos();
+    let mut states: Vec<State> = dispatchers
+        .iter()
+        .map(|d| State {
+            prev: d.progress.snapshot(),
+            progressed_at: now,
+            reported: false,
+        })
+        .collect();
+
+    loop {
+        std::thread::sleep(period);
+
+        let now = monotonic_nanos();
+        for (dispatcher, state) in dispatchers.iter().zip(&mut states) {
+            let current = dispatcher.progress.snapshot();
+            if current.started() != state.prev.started() || current.pending() == 0 {
+                state.progressed_at = now;
+            }
+            state.prev = current;
+
+            let starved = Duration::from_nanos(now - state.progressed_at);
+            let kind = if !current.is_alive() {
+                Some(StallKind::Died)
+            } else if let Some(time) = current.running_for(now).filter(|&t| t > timeout) {
+                Some(StallKind::Overrun(time))
+            } else if starved > timeout {
+                Some(StallKind::Starved(starved))
+            } else {
+                None
+            };
+
+            // Dispatchers exit on shutdown
+            if shutdown.load(Ordering::Relaxed) {
+                return;
+            }
+
+            match kind {
+                Some(kind) if !state.reported => {
+                    state.reported = true;
+                    handler(&Stall {
+                        thread: dispatcher.name,
+                        priority: dispatcher.priority,
+                        kind,
+                    });
+                }
+                Some(_) => {}
+                None => state.reported = false,
+            }
+        }
+    }
+}
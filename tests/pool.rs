@@ -0,0 +1,180 @@
+//! Tests for `rtic::pool`
+//!
+//! Slots are freed concurrently through the free list of `rtic::slab`. `free_concurrently` is also
+//! model checked with loom:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --test pool
+//! ```
+
+use rtic::pool::{Box, Pool};
+
+#[cfg(loom)]
+use loom::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+#[cfg(not(loom))]
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+};
+
+/// Runs `f` under loom model checker or repeatedly on regular threads
+fn model<F>(f: F)
+where
+    F: Fn() + Sync + Send + 'static,
+{
+    #[cfg(loom)]
+    loom::model(f);
+    #[cfg(not(loom))]
+    for _ in 0..1000 {
+        f();
+    }
+}
+
+/// Pools hand out `'static` handles, so each test gets its own leaked pool
+fn pool<T, const N: usize>() -> &'static Pool<T, N> {
+    std::boxed::Box::leak(std::boxed::Box::new(Pool::new()))
+}
+
+struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+#[cfg(not(loom))]
+fn alloc_when_exhausted() {
+    let pool = pool::<u32, 2>();
+
+    let a = pool.alloc(1).unwrap();
+    let b = pool.alloc(2).unwrap();
+    assert_eq!(pool.available(), 0);
+
+    // Value is handed back when there is no free slot
+    assert_eq!(pool.alloc(3).unwrap_err(), 3);
+    assert_eq!((*a, *b), (1, 2));
+
+    drop(a);
+    assert_eq!(*pool.alloc(4).unwrap(), 4);
+}
+
+#[test]
+#[cfg(not(loom))]
+fn drop_returns_slot() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let pool = pool::<Counted, 2>();
+
+    let a = pool.alloc(Counted(drops.clone())).ok().unwrap();
+    let b = pool.alloc(Counted(drops.clone())).ok().unwrap();
+    assert_eq!(pool.available(), 0);
+
+    // Value is dropped in place
+    drop(a);
+    assert_eq!(drops.load(Ordering::Relaxed), 1);
+    assert_eq!(pool.available(), 1);
+
+    // Value is moved out and the slot is freed without dropping it
+    let value = Box::into_inner(b);
+    assert_eq!(drops.load(Ordering::Relaxed), 1);
+    assert_eq!(pool.available(), 2);
+    drop(value);
+    assert_eq!(drops.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+#[cfg(not(loom))]
+fn concurrent_alloc() {
+    const THREADS: usize = 4;
+    let pool = pool::<usize, 8>();
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|id| {
+            thread::spawn(move || {
+                for _ in 0..10_000 {
+                    let handles: Vec<_> = (0..2).filter_map(|_| pool.alloc(id).ok()).collect();
+                    // A slot taken by another thread must never be handed out
+                    assert!(handles.iter().all(|handle| **handle == id));
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(pool.available(), 8);
+}
+
+#[test]
+#[cfg(not(loom))]
+fn free_on_other_threads() {
+    const THREADS: usize = 4;
+    let drops = Arc::new(AtomicUsize::new(0));
+    let pool = pool::<Counted, 4>();
+
+    // Handles are allocated on one thread and dropped on several others, as in tasks of different
+    // priorities
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..THREADS)
+        .map(|_| mpsc::sync_channel::<Box<_, 4>>(1))
+        .unzip();
+    let threads: Vec<_> = receivers
+        .into_iter()
+        .map(|rx| thread::spawn(move || rx.into_iter().for_each(drop)))
+        .collect();
+
+    let (mut sent, mut failed) = (0, 0);
+    while sent < 10_000 {
+        match pool.alloc(Counted(drops.clone())) {
+            Ok(handle) => {
+                senders[sent % THREADS].send(handle).ok().unwrap();
+                sent += 1;
+            }
+            // Rejected value is dropped here
+            Err(_) => failed += 1,
+        }
+    }
+
+    drop(senders);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(drops.load(Ordering::Relaxed), sent + failed);
+    assert_eq!(pool.available(), 4);
+}
+
+#[test]
+fn free_concurrently() {
+    model(|| {
+        let pool = pool::<usize, 2>();
+        let a = pool.alloc(1).unwrap();
+        let b = pool.alloc(2).unwrap();
+
+        // Each thread returns a slot and takes one, which may be the slot freed by the other
+        let t1 = thread::spawn(move || {
+            drop(a);
+            pool.alloc(3).unwrap()
+        });
+        let t2 = thread::spawn(move || {
+            assert_eq!(Box::into_inner(b), 2);
+            pool.alloc(4).unwrap()
+        });
+
+        // A slot must never be handed out twice
+        let (c, d) = (t1.join().unwrap(), t2.join().unwrap());
+        assert_eq!((*c, *d), (3, 4));
+        assert_eq!(pool.available(), 0);
+        drop((c, d));
+        assert_eq!(pool.available(), 2);
+    });
+}
@@ -0,0 +1,58 @@
+//! Tests of the shutdown of `#[idle]` in library mode
+#![cfg(not(loom))]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rtic::{environment::Policy, sched::Mode};
+
+static CLEANED_UP: AtomicBool = AtomicBool::new(false);
+
+#[rtic::app(main = false)]
+mod app {
+    use super::CLEANED_UP;
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[idle]
+    fn idle(cx: idle::Context) -> ! {
+        loop {
+            if cx.shutdown_requested() {
+                // Slower than the dispatchers to stop
+                std::thread::sleep(Duration::from_millis(200));
+                CLEANED_UP.store(true, Ordering::SeqCst);
+                cx.shutdown_done();
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[task]
+    fn noop(_: noop::Context) {}
+}
+
+#[test]
+fn join_waits_for_idle() {
+    let handle = app::start(rtic::Config {
+        mode: Some(Mode::Normal),
+        audit: Some(Policy::Ignore),
+        ..Default::default()
+    })
+    .unwrap();
+
+    // Idle has checked for a shutdown request at least once
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    handle.request_shutdown();
+    handle.join().unwrap();
+
+    assert!(CLEANED_UP.load(Ordering::SeqCst));
+}
@@ -0,0 +1,183 @@
+//! Concurrency tests for `rtic::slab`
+//!
+//! Tests are repeated on regular threads by default. For exhaustive model checking run with loom:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --test slab
+//! ```
+
+use rtic::slab::{Slab, SlabHandle, SlabSender};
+
+#[cfg(loom)]
+use loom::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+#[cfg(not(loom))]
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+/// Runs `f` under loom model checker or repeatedly on regular threads
+fn model<F>(f: F)
+where
+    F: Fn() + Sync + Send + 'static,
+{
+    #[cfg(loom)]
+    loom::model(f);
+    #[cfg(not(loom))]
+    for _ in 0..1000 {
+        f();
+    }
+}
+
+/// Inserts `items` from a separate thread and returns successfully obtained handles
+fn spawn_sender<const N: usize>(
+    tx: SlabSender<usize, N>,
+    items: Vec<usize>,
+) -> thread::JoinHandle<Vec<SlabHandle>> {
+    thread::spawn(move || {
+        items
+            .into_iter()
+            .filter_map(|item| tx.insert(item).ok())
+            .collect()
+    })
+}
+
+#[test]
+fn concurrent_insert() {
+    model(|| {
+        let (tx, mut rx) = Slab::<usize, 2>::new().split();
+
+        let t1 = spawn_sender(tx.clone(), vec![1]);
+        let t2 = spawn_sender(tx, vec![2]);
+
+        let mut handles = t1.join().unwrap();
+        handles.extend(t2.join().unwrap());
+        assert_eq!(handles.len(), 2);
+
+        // Each sender must have received its own slot
+        let mut items: Vec<_> = handles.into_iter().map(|h| rx.remove(h)).collect();
+        items.sort_unstable();
+        assert_eq!(items, [1, 2]);
+    });
+}
+
+#[test]
+fn concurrent_insert_when_full() {
+    model(|| {
+        let (tx, mut rx) = Slab::<usize, 1>::new().split();
+
+        let t1 = spawn_sender(tx.clone(), vec![1]);
+        let t2 = spawn_sender(tx.clone(), vec![2]);
+
+        let mut handles = t1.join().unwrap();
+        handles.extend(t2.join().unwrap());
+
+        // Only one of the senders can obtain the only slot
+        assert_eq!(handles.len(), 1);
+        assert_eq!(tx.used(), 1);
+        assert_eq!(tx.high_water(), 1);
+        assert_eq!(tx.failures(), 1);
+        let item = rx.remove(handles.pop().unwrap());
+        assert_eq!(tx.used(), 0);
+        assert!(item == 1 || item == 2);
+
+        // Slot is available again
+        let handle = tx.insert(3).unwrap();
+        assert_eq!(rx.remove(handle), 3);
+    });
+}
+
+#[test]
+fn concurrent_insert_and_remove() {
+    model(|| {
+        let (tx, mut rx) = Slab::<usize, 2>::new().split();
+
+        let h1 = tx.insert(1).unwrap();
+        let h2 = tx.insert(2).unwrap();
+
+        // Inserts race with the receiver returning slots
+        let t = spawn_sender(tx.clone(), vec![3, 4]);
+
+        assert_eq!(rx.remove(h1), 1);
+        assert_eq!(rx.remove(h2), 2);
+
+        let mut items: Vec<_> = t
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|h| rx.remove(h))
+            .collect();
+        items.sort_unstable();
+
+        // Senders may observe the slab as full, but must never get a slot that is in use
+        assert!(items.iter().all(|&item| item == 3 || item == 4));
+        assert!(items.windows(2).all(|w| w[0] != w[1]));
+
+        // All slots are returned
+        let handles: Vec<_> = (5..7).map(|item| tx.insert(item).unwrap()).collect();
+        assert!(tx.insert(7).is_err());
+        for handle in handles {
+            rx.remove(handle);
+        }
+    });
+}
+
+#[test]
+fn handles_cross_threads() {
+    model(|| {
+        let (tx, mut rx) = Slab::<usize, 2>::new().split();
+
+        // Handle is created on one thread and consumed on another, as in the dispatchers
+        let h1 = tx.insert(1).unwrap();
+        let t = thread::spawn(move || {
+            let item = rx.remove(h1);
+            (rx, item)
+        });
+
+        let h2 = tx.insert(2).unwrap();
+        let (mut rx, item) = t.join().unwrap();
+        assert_eq!(item, 1);
+        assert_eq!(rx.remove(h2), 2);
+    });
+}
+
+#[test]
+fn drop_releases_unconsumed_items() {
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    model(|| {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (tx, mut rx) = Slab::<Counted, 4>::new().split();
+
+        let h1 = tx.insert(Counted(drops.clone())).ok().unwrap();
+        let h2 = tx.insert(Counted(drops.clone())).ok().unwrap();
+
+        let t = thread::spawn(move || {
+            drop(rx.remove(h1));
+            rx
+        });
+        let rx = t.join().unwrap();
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+
+        // Second item was never removed, so it must be dropped together with the slab
+        std::mem::forget(h2);
+        drop(tx);
+        drop(rx);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+    });
+}
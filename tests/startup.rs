@@ -0,0 +1,67 @@
+//! Tests of the startup of an application in library mode
+#![cfg(not(loom))]
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use rtic::{environment::Policy, sched::Mode};
+
+static STARTED: AtomicU32 = AtomicU32::new(0);
+
+// Stack of the priority 2 dispatcher can not be allocated
+#[rtic::app(main = false, stack_size = [(2, "262144G")])]
+mod app {
+    use super::STARTED;
+    use std::sync::atomic::Ordering;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        low::spawn().unwrap();
+        high::spawn().unwrap();
+
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[task(priority = 1)]
+    fn low(_: low::Context) {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[task(priority = 2)]
+    fn high(_: high::Context) {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn spawn_failure() {
+    let threads = || std::fs::read_dir("/proc/self/task").unwrap().count();
+    let before = threads();
+
+    let result = app::start(rtic::Config {
+        mode: Some(Mode::Normal),
+        audit: Some(Policy::Ignore),
+        ..Default::default()
+    });
+
+    match result {
+        Err(rtic::Error::Spawn(err)) => {
+            assert!(
+                err.to_string().contains("failed to spawn thread"),
+                "{}",
+                err
+            )
+        }
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("application started"),
+    }
+
+    // The priority 1 dispatcher was spawned, but exited without running a task
+    assert_eq!(STARTED.load(Ordering::Relaxed), 0);
+    assert_eq!(threads(), before);
+}
@@ -0,0 +1,159 @@
+//! Tests of `rtic::systemd` notifications against a local `NOTIFY_SOCKET`
+//!
+//! ```text
+//! cargo test --features systemd --test systemd
+//! ```
+
+#![cfg(all(feature = "systemd", not(loom)))]
+
+use std::{
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rtic::{environment::Policy, runtime::Progress, sched::Mode};
+
+#[rtic::app(main = false)]
+mod app {
+    use std::time::Duration;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local, init::Monotonics) {
+        (Shared {}, Local {}, init::Monotonics())
+    }
+
+    #[task]
+    fn stall(_: stall::Context) {
+        std::thread::sleep(Duration::from_millis(300));
+    }
+}
+
+// Environment is shared by all tests
+static ENV: Mutex<()> = Mutex::new(());
+
+struct NotifySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl NotifySocket {
+    fn bind(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rtic-{}-{}.sock", name, std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        std::env::set_var("NOTIFY_SOCKET", &path);
+
+        Self { socket, path }
+    }
+
+    /// Receives messages until one starts with `prefix`
+    fn wait_for(&self, prefix: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut buf = [0; 256];
+
+        while Instant::now() < deadline {
+            if let Ok(len) = self.socket.recv(&mut buf) {
+                let message = String::from_utf8_lossy(&buf[..len]).into_owned();
+                if message.starts_with(prefix) {
+                    return message;
+                }
+            }
+        }
+
+        panic!("no `{}` notification received", prefix);
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        std::env::remove_var("NOTIFY_SOCKET");
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+#[test]
+fn notify() {
+    let _env = ENV.lock().unwrap();
+
+    assert!(!rtic::systemd::notify("STATUS=test").unwrap());
+
+    let socket = NotifySocket::bind("notify");
+    assert!(rtic::systemd::status("test").unwrap());
+    assert_eq!(socket.wait_for("STATUS="), "STATUS=test");
+}
+
+#[test]
+fn liveness() {
+    static PROGRESS: Progress = Progress::new();
+
+    // Dispatcher thread has not started yet
+    let prev = PROGRESS.snapshot();
+    assert!(!PROGRESS.snapshot().is_live(&prev));
+
+    let alive = PROGRESS.alive();
+    let prev = PROGRESS.snapshot();
+    assert!(PROGRESS.snapshot().is_live(&prev));
+
+    // Idle, but a released task is not received
+    PROGRESS.release();
+    assert!(!PROGRESS.snapshot().is_live(&prev));
+
+    PROGRESS.start(true, 0);
+    let running = PROGRESS.snapshot();
+    assert!(running.is_live(&prev));
+    // Still running the same task
+    assert!(!PROGRESS.snapshot().is_live(&running));
+
+    PROGRESS.finish();
+    assert!(PROGRESS.snapshot().is_live(&running));
+
+    // Dispatcher thread exited
+    drop(alive);
+    assert!(!PROGRESS.snapshot().is_live(&running));
+}
+
+#[test]
+fn app_lifecycle() {
+    let _env = ENV.lock().unwrap();
+
+    let socket = NotifySocket::bind("app");
+    // Pet every 20ms
+    std::env::set_var("WATCHDOG_USEC", "40000");
+    std::env::remove_var("WATCHDOG_PID");
+
+    let handle = app::start(rtic::Config {
+        mode: Some(Mode::Normal),
+        audit: Some(Policy::Ignore),
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert!(socket
+        .wait_for("READY=1")
+        .contains("STATUS=Running 1 dispatcher"));
+    socket.wait_for("WATCHDOG=1");
+
+    // Watchdog is not pet while a task blocks the dispatcher
+    app::stall::spawn().unwrap();
+    socket.wait_for("STATUS=Dispatcher thread stalled");
+    socket.wait_for("STATUS=Running");
+    socket.wait_for("WATCHDOG=1");
+
+    handle.request_shutdown();
+    socket.wait_for("STOPPING=1");
+    handle.join().unwrap();
+
+    std::env::remove_var("WATCHDOG_USEC");
+}